@@ -1,10 +1,15 @@
-use std::borrow::Cow;
+use std::collections::BTreeMap;
 
 use amc::application::DerefDocument;
 use amc::application::Document;
+use amc::application::DocumentTransaction;
+use amc::application::Scalar;
+use amc::application::Value;
 use automerge::transaction::Transactable;
-use automerge::transaction::UnObserved;
+use automerge::ObjId;
 use automerge::ObjType;
+use automerge::Patch;
+use automerge::Prop;
 use automerge::ReadDoc;
 use automerge::ROOT;
 
@@ -13,11 +18,14 @@ use crate::scalar::ScalarValue;
 pub const LIST_KEY: &str = "list";
 pub const TEXT_KEY: &str = "text";
 pub const MAP_KEY: &str = "map";
+pub const TABLE_KEY: &str = "table";
 
 /// The app that clients work with.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct AppState {
-    doc: Cow<'static, Document>,
+    doc: Document,
+    /// View of the document kept up to date from patches, if the document is observing them.
+    view: Option<Value>,
 }
 
 impl DerefDocument for AppState {
@@ -26,21 +34,36 @@ impl DerefDocument for AppState {
     }
 
     fn document_mut(&mut self) -> &mut Document {
-        self.doc.to_mut()
+        &mut self.doc
     }
 }
 
 impl AppState {
-    pub fn new(id: usize) -> Self {
-        let mut doc = Document::new(id);
+    pub fn new(mut doc: Document) -> Self {
         doc.with_initial_change(|txn| {
             // create objects we'll be working in
             txn.put_object(ROOT, MAP_KEY, ObjType::Map).unwrap();
             txn.put_object(ROOT, LIST_KEY, ObjType::List).unwrap();
             txn.put_object(ROOT, TEXT_KEY, ObjType::Text).unwrap();
+            txn.put_object(ROOT, TABLE_KEY, ObjType::Table).unwrap();
         });
-        Self {
-            doc: Cow::Owned(doc),
+        Self { doc, view: None }
+    }
+
+    /// Get the view of the document built from patches.
+    pub fn view(&self) -> Option<&Value> {
+        self.view.as_ref()
+    }
+
+    /// Apply patches to the view, building it from empty if `reset` is set.
+    pub fn apply_patches(&mut self, reset: bool, patches: Vec<Patch>) {
+        if reset {
+            self.view = Some(Value::Map(BTreeMap::new()));
+        }
+        if let Some(view) = &mut self.view {
+            for patch in patches {
+                apply_patch(view, &self.doc, patch);
+            }
         }
     }
 
@@ -53,9 +76,7 @@ impl AppState {
             .unwrap()
     }
 
-    fn get_map_obj_tx(
-        tx: &mut automerge::transaction::Transaction<UnObserved>,
-    ) -> automerge::ObjId {
+    fn get_map_obj_tx(tx: &mut DocumentTransaction) -> automerge::ObjId {
         tx.get(ROOT, MAP_KEY)
             .ok()
             .flatten()
@@ -63,6 +84,23 @@ impl AppState {
             .unwrap()
     }
 
+    fn get_table_obj(&self) -> automerge::ObjId {
+        self.doc
+            .get(ROOT, TABLE_KEY)
+            .ok()
+            .flatten()
+            .map(|(_, id)| id)
+            .unwrap()
+    }
+
+    fn get_table_obj_tx(tx: &mut DocumentTransaction) -> automerge::ObjId {
+        tx.get(ROOT, TABLE_KEY)
+            .ok()
+            .flatten()
+            .map(|(_, id)| id)
+            .unwrap()
+    }
+
     fn get_list_obj(&self) -> automerge::ObjId {
         self.doc
             .get(ROOT, LIST_KEY)
@@ -72,9 +110,7 @@ impl AppState {
             .unwrap()
     }
 
-    fn get_list_obj_tx(
-        tx: &mut automerge::transaction::Transaction<UnObserved>,
-    ) -> automerge::ObjId {
+    fn get_list_obj_tx(tx: &mut DocumentTransaction) -> automerge::ObjId {
         tx.get(ROOT, LIST_KEY)
             .ok()
             .flatten()
@@ -91,9 +127,7 @@ impl AppState {
             .unwrap()
     }
 
-    fn get_text_obj_tx(
-        tx: &mut automerge::transaction::Transaction<UnObserved>,
-    ) -> automerge::ObjId {
+    fn get_text_obj_tx(tx: &mut DocumentTransaction) -> automerge::ObjId {
         tx.get(ROOT, TEXT_KEY)
             .ok()
             .flatten()
@@ -102,15 +136,22 @@ impl AppState {
     }
 
     pub fn put_map(&mut self, key: String, value: ScalarValue) {
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         let map = Self::get_map_obj_tx(&mut tx);
         tx.put(map, key, value).unwrap();
         tx.commit();
     }
 
+    pub fn put_table(&mut self, key: String, value: ScalarValue) {
+        let mut tx = self.doc.transaction();
+        let table = Self::get_table_obj_tx(&mut tx);
+        tx.put(table, key, value).unwrap();
+        tx.commit();
+    }
+
     pub fn put_list(&mut self, index: usize, value: ScalarValue) {
         let list = self.get_list_obj();
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         if tx.put(list, index, value).is_err() {
             tx.rollback();
             return;
@@ -120,7 +161,7 @@ impl AppState {
 
     pub fn put_text(&mut self, index: usize, value: String) {
         let text = self.get_text_obj();
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         if tx.put(text, index, value).is_err() {
             tx.rollback();
             return;
@@ -130,7 +171,7 @@ impl AppState {
 
     pub fn insert_list(&mut self, index: usize, value: ScalarValue) {
         let list = self.get_list_obj();
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         if tx.insert(list, index, value).is_err() {
             tx.rollback();
             return;
@@ -140,7 +181,7 @@ impl AppState {
 
     pub fn insert_text(&mut self, index: usize, value: String) {
         let text = self.get_text_obj();
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         if tx.insert(text, index, value).is_err() {
             tx.rollback();
             return;
@@ -149,14 +190,21 @@ impl AppState {
     }
 
     pub fn delete(&mut self, key: &str) {
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         let map = Self::get_map_obj_tx(&mut tx);
         tx.delete(map, key).unwrap();
         tx.commit();
     }
 
+    pub fn delete_table(&mut self, key: &str) {
+        let mut tx = self.doc.transaction();
+        let table = Self::get_table_obj_tx(&mut tx);
+        tx.delete(table, key).unwrap();
+        tx.commit();
+    }
+
     pub fn delete_list(&mut self, index: usize) {
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         let list = Self::get_list_obj_tx(&mut tx);
         if tx.delete(list, index).is_err() {
             tx.rollback();
@@ -167,17 +215,17 @@ impl AppState {
 
     pub fn delete_text(&mut self, index: usize) {
         let text = self.get_text_obj();
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         if tx.delete(text, index).is_err() {
             tx.rollback();
-            self.doc.to_mut().set_error();
+            self.doc.set_error();
             return;
         };
         tx.commit();
     }
 
     pub fn splice_list(&mut self, index: usize, delete: usize, values: Vec<ScalarValue>) {
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         let list = Self::get_list_obj_tx(&mut tx);
         let values = values.into_iter().map(Into::into);
         if tx.splice(list, index, delete, values).is_err() {
@@ -188,7 +236,7 @@ impl AppState {
     }
 
     pub fn splice_text(&mut self, index: usize, delete: usize, value: String) {
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         let text = Self::get_text_obj_tx(&mut tx);
         if tx.splice_text(text, index, delete, &value).is_err() {
             tx.rollback();
@@ -198,7 +246,7 @@ impl AppState {
     }
 
     pub fn increment_map(&mut self, key: String, by: i64) {
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         let map = Self::get_map_obj_tx(&mut tx);
         tx.increment(map, key, by).unwrap();
         tx.commit();
@@ -206,7 +254,7 @@ impl AppState {
 
     pub fn increment_list(&mut self, index: usize, by: i64) {
         let list = self.get_list_obj();
-        let mut tx = self.doc.to_mut().transaction();
+        let mut tx = self.doc.transaction();
         if tx.increment(list, index, by).is_err() {
             tx.rollback();
             return;
@@ -234,4 +282,133 @@ impl AppState {
     pub fn map_contains(&self, key: &str) -> bool {
         matches!(self.doc.get(self.get_map_obj(), key), Ok(Some(_)))
     }
+
+    pub fn table_contains(&self, key: &str) -> bool {
+        matches!(self.doc.get(self.get_table_obj(), key), Ok(Some(_)))
+    }
+}
+
+/// Apply a single patch to the view, reading exposed objects from the document.
+fn apply_patch(view: &mut Value, doc: &Document, patch: Patch) {
+    match patch {
+        Patch::Put {
+            path, prop, value, ..
+        } => put(view, &path, prop, patch_value(value.0)),
+        Patch::Expose { path, prop, .. } => {
+            // the object was hidden behind a conflicting value, so has contents that no patches
+            // describe
+            let keys = path
+                .iter()
+                .map(|(_, prop)| prop)
+                .chain(std::iter::once(&prop))
+                .map(|prop| match prop {
+                    Prop::Map(key) => key.clone(),
+                    Prop::Seq(index) => index.to_string(),
+                })
+                .collect::<Vec<_>>();
+            let keys = keys.iter().map(String::as_str).collect::<Vec<_>>();
+            if let Some(value) = doc.value_at(&keys) {
+                put(view, &path, prop, value);
+            }
+        }
+        Patch::Insert {
+            path, index, value, ..
+        } => {
+            let value = patch_value(value.0);
+            match object_at(view, &path) {
+                Some(Value::List(list)) if index <= list.len() => list.insert(index, value),
+                Some(Value::Text(text)) => {
+                    if let Value::Scalar(Scalar::Str(s)) = value {
+                        let mut chars = text.chars().map(String::from).collect::<Vec<_>>();
+                        if index <= chars.len() {
+                            chars.insert(index, s);
+                        }
+                        *text = chars.concat();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Patch::Increment {
+            path, prop, value, ..
+        } => {
+            if let Some(Value::Scalar(Scalar::Counter(counter))) =
+                object_at(view, &path).and_then(|object| child(object, &prop))
+            {
+                *counter += value.0;
+            }
+        }
+        Patch::Delete {
+            path, prop, num, ..
+        } => match (object_at(view, &path), prop) {
+            (Some(Value::Map(map) | Value::Table(map)), Prop::Map(key)) => {
+                map.remove(&key);
+            }
+            (Some(Value::List(list)), Prop::Seq(index)) => {
+                let end = (index + num).min(list.len());
+                list.drain(index.min(end)..end);
+            }
+            (Some(Value::Text(text)), Prop::Seq(index)) => {
+                *text = text
+                    .chars()
+                    .enumerate()
+                    .filter(|(i, _)| *i < index || *i >= index + num)
+                    .map(|(_, c)| c)
+                    .collect();
+            }
+            _ => {}
+        },
+    }
+}
+
+/// Put a value at a prop of the object in the view at the path.
+fn put(view: &mut Value, path: &[(ObjId, Prop)], prop: Prop, value: Value) {
+    match (object_at(view, path), prop) {
+        (Some(Value::Map(map) | Value::Table(map)), Prop::Map(key)) => {
+            map.insert(key, value);
+        }
+        (Some(Value::List(list)), Prop::Seq(index)) => {
+            if let Some(element) = list.get_mut(index) {
+                *element = value;
+            }
+        }
+        (Some(Value::Text(text)), Prop::Seq(index)) => {
+            if let Value::Scalar(Scalar::Str(s)) = value {
+                let mut chars = text.chars().map(String::from).collect::<Vec<_>>();
+                if let Some(c) = chars.get_mut(index) {
+                    *c = s;
+                }
+                *text = chars.concat();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Convert a value from a patch, new objects start out empty.
+fn patch_value(value: automerge::Value<'_>) -> Value {
+    match value {
+        automerge::Value::Object(ObjType::Map) => Value::Map(BTreeMap::new()),
+        automerge::Value::Object(ObjType::Table) => Value::Table(BTreeMap::new()),
+        automerge::Value::Object(ObjType::List) => Value::List(Vec::new()),
+        automerge::Value::Object(ObjType::Text) => Value::Text(String::new()),
+        automerge::Value::Scalar(s) => Value::Scalar(Scalar::from(s.as_ref())),
+    }
+}
+
+/// Find the object in the view at the path from the root.
+fn object_at<'a>(mut view: &'a mut Value, path: &[(ObjId, Prop)]) -> Option<&'a mut Value> {
+    for (_, prop) in path {
+        view = child(view, prop)?;
+    }
+    Some(view)
+}
+
+/// Get the value at a prop of an object in the view.
+fn child<'a>(object: &'a mut Value, prop: &Prop) -> Option<&'a mut Value> {
+    match (object, prop) {
+        (Value::Map(map) | Value::Table(map), Prop::Map(key)) => map.get_mut(key),
+        (Value::List(list), Prop::Seq(index)) => list.get_mut(*index),
+        _ => None,
+    }
 }
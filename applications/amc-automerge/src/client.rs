@@ -12,8 +12,12 @@ mod put;
 mod splice;
 
 use amc::application::Application;
+use amc::application::Document;
+use amc::application::Value;
+use automerge::Patch;
 pub use delete::ListDeleter;
 pub use delete::MapSingleDeleter;
+pub use delete::TableSingleDeleter;
 pub use delete::TextDeleter;
 pub use increment::ListIncrementer;
 pub use increment::MapIncrementer;
@@ -21,6 +25,7 @@ pub use insert::ListInserter;
 pub use insert::TextInserter;
 pub use put::ListPutter;
 pub use put::MapSinglePutter;
+pub use put::TableSinglePutter;
 pub use put::TextPutter;
 pub use splice::ListSplicer;
 pub use splice::TextSplicer;
@@ -30,6 +35,8 @@ pub struct App {
     pub map_single_putter: put::MapSinglePutter,
     pub map_single_deleter: delete::MapSingleDeleter,
     pub map_incrementer: increment::MapIncrementer,
+    pub table_single_putter: put::TableSinglePutter,
+    pub table_single_deleter: delete::TableSingleDeleter,
     pub list_putter: put::ListPutter,
     pub list_deleter: delete::ListDeleter,
     pub list_inserter: insert::ListInserter,
@@ -48,8 +55,8 @@ impl Application for App {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(&self, document: &mut Cow<Self::State>, input: Self::Input) -> Option<()> {
@@ -61,6 +68,12 @@ impl Application for App {
             DriverMsg::MapIncrement { key, by } => {
                 self.map_incrementer.execute(document, (key, by))
             }
+            DriverMsg::TableSinglePut { key, value } => {
+                self.table_single_putter.execute(document, (key, value))
+            }
+            DriverMsg::TableSingleDelete { key } => {
+                self.table_single_deleter.execute(document, key)
+            }
             DriverMsg::ListPut { index, value } => {
                 self.list_putter.execute(document, (index, value))
             }
@@ -91,4 +104,12 @@ impl Application for App {
         };
         None
     }
+
+    fn on_patches(&self, state: &mut Cow<Self::State>, reset: bool, patches: Vec<Patch>) {
+        state.to_mut().apply_patches(reset, patches);
+    }
+
+    fn patched_view(&self, state: &Self::State) -> Option<Value> {
+        state.view().cloned()
+    }
 }
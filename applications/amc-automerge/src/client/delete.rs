@@ -1,6 +1,6 @@
 use crate::app::AppState;
 
-use super::Application;
+use super::{Application, Document};
 
 /// A client strategy that just deletes a single key in a map.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -13,8 +13,8 @@ impl Application for MapSingleDeleter {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -29,6 +29,33 @@ impl Application for MapSingleDeleter {
     }
 }
 
+/// A client strategy that just deletes a single key in a table.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TableSingleDeleter;
+
+impl Application for TableSingleDeleter {
+    type Input = String;
+
+    type Output = ();
+
+    type State = AppState;
+
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
+    }
+
+    fn execute(
+        &self,
+        document: &mut std::borrow::Cow<Self::State>,
+        input: Self::Input,
+    ) -> Option<()> {
+        if document.table_contains(&input) {
+            document.to_mut().delete_table(&input);
+        }
+        None
+    }
+}
+
 /// A client strategy that just deletes the first element in a list.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct ListDeleter;
@@ -40,8 +67,8 @@ impl Application for ListDeleter {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -67,8 +94,8 @@ impl Application for TextDeleter {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -1,6 +1,6 @@
 use crate::app::AppState;
 
-use super::Application;
+use super::{Application, Document};
 
 /// A client strategy that increments a counter in a map.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -13,8 +13,8 @@ impl Application for MapIncrementer {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -37,8 +37,8 @@ impl Application for ListIncrementer {
     type Output = ();
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -2,7 +2,7 @@ use std::borrow::Cow;
 
 use crate::{app::AppState, scalar::ScalarValue};
 
-use super::Application;
+use super::{Application, Document};
 
 /// A client strategy that just inserts at the start of the list.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -15,8 +15,8 @@ impl Application for ListInserter {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(&self, document: &mut Cow<Self::State>, (index, value): Self::Input) -> Option<()> {
@@ -38,8 +38,8 @@ impl Application for TextInserter {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(&self, document: &mut Cow<Self::State>, (index, value): Self::Input) -> Option<()> {
@@ -1,6 +1,6 @@
 use crate::{app::AppState, scalar::ScalarValue};
 
-use super::Application;
+use super::{Application, Document};
 
 /// A client strategy that just puts at a single key into a map.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -13,8 +13,8 @@ impl Application for MapSinglePutter {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -27,6 +27,31 @@ impl Application for MapSinglePutter {
     }
 }
 
+/// A client strategy that just puts at a single key into a table.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TableSinglePutter;
+
+impl Application for TableSinglePutter {
+    type Input = (String, ScalarValue);
+
+    type Output = ();
+
+    type State = AppState;
+
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
+    }
+
+    fn execute(
+        &self,
+        document: &mut std::borrow::Cow<Self::State>,
+        (key, value): Self::Input,
+    ) -> Option<()> {
+        document.to_mut().put_table(key, value);
+        None
+    }
+}
+
 /// A client strategy that just puts at the start of a list.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct ListPutter;
@@ -37,8 +62,8 @@ impl Application for ListPutter {
     type Output = ();
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
@@ -63,8 +88,8 @@ impl Application for TextPutter {
     type Output = ();
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id)
+    fn init(&self, _id: usize, document: Document) -> Self::State {
+        AppState::new(document)
     }
 
     fn execute(
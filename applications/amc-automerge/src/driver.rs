@@ -17,6 +17,13 @@ pub enum DriverState {
     MapSingleDelete {
         key: String,
     },
+    TableSinglePut {
+        key: String,
+        value: ScalarValue,
+    },
+    TableSingleDelete {
+        key: String,
+    },
     ListPut {
         index: usize,
         value: ScalarValue,
@@ -74,6 +81,13 @@ pub enum DriverMsg {
         key: String,
         by: i64,
     },
+    TableSinglePut {
+        key: String,
+        value: ScalarValue,
+    },
+    TableSingleDelete {
+        key: String,
+    },
     ListPut {
         index: usize,
         value: ScalarValue,
@@ -132,6 +146,15 @@ impl Drive<App> for Driver {
             DriverState::MapSingleDelete { key } => {
                 vec![DriverMsg::MapSingleDelete { key: key.clone() }]
             }
+            DriverState::TableSinglePut { key, value } => {
+                vec![DriverMsg::TableSinglePut {
+                    key: key.clone(),
+                    value: value.clone(),
+                }]
+            }
+            DriverState::TableSingleDelete { key } => {
+                vec![DriverMsg::TableSingleDelete { key: key.clone() }]
+            }
             DriverState::ListPut { index, value } => {
                 vec![DriverMsg::ListPut {
                     index: *index,
@@ -1,8 +1,8 @@
 use std::sync::Arc;
 
-use crate::app::{LIST_KEY, MAP_KEY};
+use crate::app::{LIST_KEY, MAP_KEY, TABLE_KEY};
 use crate::client::App;
-use crate::scalar::ScalarValue;
+use crate::scalar::{ScalarValue, F64};
 use amc::global::{GlobalActor, GlobalActorState};
 use app::TEXT_KEY;
 
@@ -23,6 +23,8 @@ pub enum ObjectType {
     List,
     /// Use a text object in Automerge.
     Text,
+    /// Use a table object in Automerge.
+    Table,
 }
 
 #[derive(Parser, Debug)]
@@ -47,6 +49,10 @@ pub struct AutomergeOpts {
     #[clap(long, global = true)]
     pub uint: bool,
 
+    /// Use the float type.
+    #[clap(long, global = true)]
+    pub float: bool,
+
     /// Use the timestamp type.
     #[clap(long, global = true)]
     pub timestamp: bool,
@@ -59,7 +65,7 @@ pub struct AutomergeOpts {
     #[clap(long, global = true)]
     pub null: bool,
 
-    /// Keys to use if using a map.
+    /// Keys to use if using a map or table.
     #[clap(long, global = true, default_value = "foo", value_delimiter = ',')]
     pub keys: Vec<String>,
 
@@ -114,6 +120,8 @@ impl amc::model::ModelBuilder for AutomergeOpts {
             map_single_putter: client::MapSinglePutter,
             map_single_deleter: client::MapSingleDeleter,
             map_incrementer: client::MapIncrementer,
+            table_single_putter: client::TableSinglePutter,
+            table_single_deleter: client::TableSingleDeleter,
             list_putter: client::ListPutter,
             list_deleter: client::ListDeleter,
             list_inserter: client::ListInserter,
@@ -166,6 +174,31 @@ impl amc::model::ModelBuilder for AutomergeOpts {
                         d
                     })
                     .collect(),
+                ObjectType::Table => self
+                    .keys
+                    .iter()
+                    .flat_map(|k| {
+                        let mut d = vec![];
+                        if self.put {
+                            d.push(Driver {
+                                func: crate::driver::DriverState::TableSinglePut {
+                                    key: k.to_owned(),
+                                    value: value.clone(),
+                                },
+                                repeats: self.repeats,
+                            })
+                        }
+                        if self.delete {
+                            d.push(Driver {
+                                func: crate::driver::DriverState::TableSingleDelete {
+                                    key: k.to_owned(),
+                                },
+                                repeats: self.repeats,
+                            })
+                        }
+                        d
+                    })
+                    .collect(),
                 ObjectType::List => self
                     .indices
                     .iter()
@@ -291,6 +324,10 @@ impl amc::model::ModelBuilder for AutomergeOpts {
             let value = ScalarValue::Uint(server as u64);
             add_drivers(value);
         }
+        if self.float {
+            let value = ScalarValue::F64(F64(server as f64 + 0.5));
+            add_drivers(value);
+        }
         if self.timestamp {
             let value = ScalarValue::Timestamp(server as i64);
             add_drivers(value);
@@ -315,6 +352,11 @@ impl amc::model::ModelBuilder for AutomergeOpts {
                 // don't add to it otherwise
                 0
             },
+            max_table_size: if self.object_type == ObjectType::Table {
+                self.keys.len()
+            } else {
+                0
+            },
             max_list_size: if self.object_type == ObjectType::List {
                 // each server performs an insert to the indices repeated some number of times
                 model_opts.servers * self.repeats as usize * self.indices.len()
@@ -356,6 +398,18 @@ impl amc::model::ModelBuilder for AutomergeOpts {
                     .iter()
                     .all(|s| max_map_size_is_the_max(s, &model.cfg))
             }),
+            Prop::sometimes("reach max table size", |model, state| {
+                state
+                    .actor_states
+                    .iter()
+                    .any(|s| state_has_max_table_size(s, &model.cfg))
+            }),
+            Prop::always("max table size is the max", |model, state| {
+                state
+                    .actor_states
+                    .iter()
+                    .all(|s| max_table_size_is_the_max(s, &model.cfg))
+            }),
             Prop::sometimes("reach max list size", |model, state| {
                 state
                     .actor_states
@@ -402,6 +456,24 @@ fn max_map_size_is_the_max(state: &Arc<ActorState>, cfg: &Config) -> bool {
     }
 }
 
+fn state_has_max_table_size(state: &Arc<ActorState>, cfg: &Config) -> bool {
+    let max = cfg.max_table_size;
+    if let GlobalActorState::Server(s) = &**state {
+        s.length(TABLE_KEY) == max
+    } else {
+        false
+    }
+}
+
+fn max_table_size_is_the_max(state: &Arc<ActorState>, cfg: &Config) -> bool {
+    let max = cfg.max_table_size;
+    if let GlobalActorState::Server(s) = &**state {
+        s.length(TABLE_KEY) <= max
+    } else {
+        true
+    }
+}
+
 fn state_has_max_list_size(state: &Arc<ActorState>, cfg: &Config) -> bool {
     let max = cfg.max_list_size;
     if let GlobalActorState::Server(s) = &**state {
@@ -441,6 +513,170 @@ fn max_text_size_is_the_max(state: &Arc<ActorState>, cfg: &Config) -> bool {
 #[derive(Debug, Clone)]
 pub struct Config {
     pub max_map_size: usize,
+    pub max_table_size: usize,
     pub max_list_size: usize,
     pub max_text_size: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use amc::{
+        application::{server::SyncMethod, DerefDocument, Document},
+        model::ModelOpts,
+    };
+    use automerge::{transaction::Transactable, ActorId, Automerge, ObjType, ROOT};
+    use stateright::{Checker, Model};
+
+    use crate::app::AppState;
+
+    use super::*;
+
+    fn check_patched_view(sync_method: SyncMethod, automerge_opts: AutomergeOpts) {
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method,
+            observe_patches: true,
+            checks: vec!["patched-view".to_owned()],
+            ..Default::default()
+        };
+        let checker = model_opts
+            .to_model(&automerge_opts)
+            .checker()
+            .spawn_bfs()
+            .join();
+        assert!(checker
+            .discovery("patched view matches the document")
+            .is_none());
+    }
+
+    fn automerge_opts(object_type: ObjectType) -> AutomergeOpts {
+        AutomergeOpts {
+            object_type,
+            bytes: false,
+            string: false,
+            int: false,
+            uint: false,
+            float: false,
+            timestamp: false,
+            boolean: false,
+            null: false,
+            keys: vec!["foo".to_owned()],
+            indices: vec![0],
+            put: false,
+            insert: false,
+            delete: false,
+            splice: false,
+            repeats: 1,
+        }
+    }
+
+    #[test]
+    fn table_floats() {
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::Messages,
+            in_sync_check: true,
+            save_load_check: true,
+            ..Default::default()
+        };
+        let automerge_opts = AutomergeOpts {
+            float: true,
+            put: true,
+            delete: true,
+            ..automerge_opts(ObjectType::Table)
+        };
+
+        let checker = model_opts
+            .to_model(&automerge_opts)
+            .checker()
+            .spawn_bfs()
+            .join();
+        assert!(checker.discovery("reach max table size").is_some());
+        assert!(checker.discovery("max table size is the max").is_none());
+        assert!(checker
+            .discovery("in sync when syncing is done and no in-flight requests")
+            .is_none());
+        assert!(checker
+            .discovery("saving and loading the document gives the same document")
+            .is_none());
+    }
+
+    #[test]
+    fn patched_view_exposed_object() {
+        let mut doc = Document::new(0);
+        doc.observe_patches();
+        let mut state = AppState::new(doc);
+        let patches = state.document_mut().take_patches();
+        state.apply_patches(true, patches);
+
+        // an object with contents that will lose a conflict
+        let mut txn = state.document_mut().transaction();
+        let obj = txn.put_object(ROOT, "obj", ObjType::Map).unwrap();
+        txn.put(&obj, "x", 1).unwrap();
+        txn.commit();
+
+        // a peer puts an empty object there with a higher counter, so it wins the conflict
+        let mut peer = Automerge::new();
+        peer.set_actor(ActorId::from(1usize.to_be_bytes()));
+        let mut txn = peer.transaction();
+        for i in 0..10 {
+            txn.put(ROOT, format!("pad{}", i), i).unwrap();
+        }
+        txn.put_object(ROOT, "obj", ObjType::Map).unwrap();
+        txn.commit();
+        // then deletes it, without having seen ours
+        let mut deleted = peer.fork();
+        let mut txn = deleted.transaction();
+        txn.delete(ROOT, "obj").unwrap();
+        txn.commit();
+
+        for other in [&mut peer, &mut deleted] {
+            state.document_mut().merge(other);
+            let patches = state.document_mut().take_patches();
+            state.apply_patches(false, patches);
+        }
+
+        // our object is exposed again, along with its contents
+        assert_eq!(state.view(), Some(&state.document().materialize()));
+        assert!(state.document().value_at(&["obj", "x"]).is_some());
+    }
+
+    #[test]
+    fn patched_view_map() {
+        check_patched_view(
+            SyncMethod::Changes,
+            AutomergeOpts {
+                int: true,
+                put: true,
+                delete: true,
+                ..automerge_opts(ObjectType::Map)
+            },
+        );
+    }
+
+    #[test]
+    fn patched_view_list() {
+        check_patched_view(
+            SyncMethod::Messages,
+            AutomergeOpts {
+                int: true,
+                insert: true,
+                delete: true,
+                ..automerge_opts(ObjectType::List)
+            },
+        );
+    }
+
+    #[test]
+    fn patched_view_text() {
+        check_patched_view(
+            SyncMethod::Messages,
+            AutomergeOpts {
+                string: true,
+                insert: true,
+                delete: true,
+                ..automerge_opts(ObjectType::Text)
+            },
+        );
+    }
+}
@@ -1,16 +1,41 @@
+use std::hash::Hash;
+
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum ScalarValue {
     Bytes(Vec<u8>),
     Str(String),
     Int(i64),
     Uint(u64),
+    F64(F64),
     /// Counter with initial value.
     Counter(i64),
     Timestamp(i64),
     Boolean(bool),
+    Unknown {
+        type_code: u8,
+        bytes: Vec<u8>,
+    },
     Null,
 }
 
+/// A float that can be hashed and compared for equality, by its bits.
+#[derive(Clone, Copy, Debug)]
+pub struct F64(pub f64);
+
+impl PartialEq for F64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for F64 {}
+
+impl Hash for F64 {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
 impl From<automerge::ScalarValue> for ScalarValue {
     fn from(s: automerge::ScalarValue) -> Self {
         match s {
@@ -18,15 +43,14 @@ impl From<automerge::ScalarValue> for ScalarValue {
             automerge::ScalarValue::Str(s) => Self::Str(s.into()),
             automerge::ScalarValue::Int(i) => Self::Int(i),
             automerge::ScalarValue::Uint(u) => Self::Uint(u),
-            automerge::ScalarValue::F64(_) => todo!(),
+            automerge::ScalarValue::F64(f) => Self::F64(F64(f)),
             // TODO: expose counter type in automerge to get initial value
             automerge::ScalarValue::Counter(_) => Self::Counter(0),
             automerge::ScalarValue::Timestamp(t) => Self::Timestamp(t),
             automerge::ScalarValue::Boolean(b) => Self::Boolean(b),
-            automerge::ScalarValue::Unknown {
-                type_code: _,
-                bytes: _,
-            } => todo!(),
+            automerge::ScalarValue::Unknown { type_code, bytes } => {
+                Self::Unknown { type_code, bytes }
+            }
             automerge::ScalarValue::Null => Self::Null,
         }
     }
@@ -39,9 +63,13 @@ impl From<ScalarValue> for automerge::ScalarValue {
             ScalarValue::Str(s) => automerge::ScalarValue::Str(s.into()),
             ScalarValue::Int(i) => automerge::ScalarValue::Int(i),
             ScalarValue::Uint(u) => automerge::ScalarValue::Uint(u),
+            ScalarValue::F64(F64(f)) => automerge::ScalarValue::F64(f),
             ScalarValue::Counter(i) => automerge::ScalarValue::counter(i),
             ScalarValue::Timestamp(t) => automerge::ScalarValue::Timestamp(t),
             ScalarValue::Boolean(b) => automerge::ScalarValue::Boolean(b),
+            ScalarValue::Unknown { type_code, bytes } => {
+                automerge::ScalarValue::Unknown { type_code, bytes }
+            }
             ScalarValue::Null => automerge::ScalarValue::Null,
         }
     }
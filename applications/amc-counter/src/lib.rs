@@ -48,8 +48,7 @@ impl Application for Counter {
     type Output = ();
     type State = CounterState;
 
-    fn init(&self, id: usize) -> Self::State {
-        let mut doc = Document::new(id);
+    fn init(&self, _id: usize, mut doc: Document) -> Self::State {
         if self.initial_change {
             doc.with_initial_change(|txn| {
                 txn.put(ROOT, "counter", ScalarValue::counter(0)).unwrap();
@@ -297,7 +296,15 @@ pub struct Config {}
 
 #[cfg(test)]
 mod tests {
-    use amc::{application::server::SyncMethod, model::ModelOpts};
+    use amc::{
+        application::{
+            server::{Fault, PartitionMode, RestartMode, SyncMethod},
+            StateHashing,
+        },
+        model::{ModelOpts, NetworkType, Topology},
+    };
+    use amc::{properties::all_same_heads, report::Reporter};
+    use stateright::{Checker, Expectation, Model};
 
     use expect_test::expect;
 
@@ -308,12 +315,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let counter_opts = CounterOpts {
             counter_type: false,
@@ -341,12 +343,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let counter_opts = CounterOpts {
             counter_type: true,
@@ -374,12 +371,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let counter_opts = CounterOpts {
             counter_type: false,
@@ -407,12 +399,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let counter_opts = CounterOpts {
             counter_type: true,
@@ -429,4 +416,444 @@ mod tests {
             "#]],
         );
     }
+
+    #[test]
+    fn both_fixes_symmetry() {
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::Messages,
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 1,
+        };
+
+        amc_test::check_symmetry(model_opts, counter_opts);
+    }
+
+    #[test]
+    fn both_fixes_symmetry_three_servers() {
+        let model_opts = ModelOpts {
+            servers: 3,
+            sync_method: SyncMethod::Messages,
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+
+        // records the time and memory taken with and without symmetry
+        amc_test::check_symmetry(model_opts, counter_opts);
+    }
+
+    #[test]
+    fn both_fixes_pairs() {
+        let model_opts = ModelOpts::default();
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 1,
+        };
+
+        let outcomes = amc::commute::check_pairs(&counter_opts, &model_opts);
+        // an increment and a decrement on each of the two servers
+        assert_eq!(outcomes.len(), 4);
+        for outcome in outcomes {
+            assert!(outcome.commutes, "{:?}", outcome);
+            assert!(outcome.merge_matches_sequential, "{:?}", outcome);
+            // counting is not idempotent, running an input again counts it again
+            assert!(!outcome.idempotent, "{:?}", outcome);
+        }
+    }
+
+    #[test]
+    fn counter_type_partial_fix_pairs() {
+        let model_opts = ModelOpts::default();
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: false,
+            increments: 1,
+            decrements: 1,
+        };
+
+        // each server creates its own counter so one of them wins when merged
+        let outcomes = amc::commute::check_pairs(&counter_opts, &model_opts);
+        assert!(outcomes.iter().all(|outcome| outcome.commutes));
+        assert!(outcomes
+            .iter()
+            .all(|outcome| !outcome.merge_matches_sequential));
+    }
+
+    #[test]
+    fn faulty_peer_invalid_messages() {
+        let model_opts = ModelOpts {
+            faulty_peer: Some(Fault::Truncate),
+            checks: vec!["invalid-messages".to_owned(), "error-free".to_owned()],
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 1,
+        };
+
+        let discoveries = amc_test::discoveries(model_opts, counter_opts);
+        assert!(discoveries.contains(&"no invalid messages received"));
+        // truncated changes from the faulty peer are expected, so aren't errors
+        assert!(!discoveries.contains(&"no errors set (from panics)"));
+    }
+
+    #[test]
+    fn fully_broken_semantic_hashing() {
+        let run = |state_hashing| {
+            let model_opts = ModelOpts {
+                state_hashing,
+                // a check that holds keeps the search going after the violation is found
+                checks: vec!["error-free".to_owned()],
+                ..Default::default()
+            };
+            let counter_opts = CounterOpts {
+                counter_type: false,
+                initial_change: false,
+                increments: 1,
+                decrements: 1,
+            };
+            amc_test::explore(model_opts, counter_opts)
+        };
+
+        let (exact_states, exact_discoveries) = run(StateHashing::Exact);
+        let (semantic_states, semantic_discoveries) = run(StateHashing::Semantic);
+        assert!(
+            semantic_states < exact_states,
+            "semantic hashing explored {} states, exact hashing {}",
+            semantic_states,
+            exact_states
+        );
+        assert_eq!(exact_discoveries, vec!["correct value"]);
+        assert_eq!(semantic_discoveries, vec!["correct value"]);
+    }
+
+    #[test]
+    fn both_fixes_convergence() {
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::Messages,
+            checks: vec!["convergence".to_owned()],
+            unproductive_sync_bound: Some(4),
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 1,
+        };
+
+        let discoveries = amc_test::discoveries(model_opts, counter_opts);
+        assert!(!discoveries.contains(&"servers converge once drivers are done"));
+        assert!(!discoveries.contains(&"syncing makes progress"));
+    }
+
+    #[test]
+    fn convergence_seeded_livelock() {
+        // every round of syncing ends with a message that has nothing new, so a bound of one
+        // reports a livelock straight away
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::Messages,
+            checks: vec!["convergence".to_owned()],
+            unproductive_sync_bound: Some(1),
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 1,
+        };
+
+        let discoveries = amc_test::discoveries(model_opts, counter_opts);
+        assert!(discoveries.contains(&"syncing makes progress"));
+    }
+
+    #[test]
+    #[should_panic(expected = "the convergence check needs an unproductive sync bound")]
+    fn convergence_needs_bound() {
+        let model_opts = ModelOpts {
+            checks: vec!["convergence".to_owned()],
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 1,
+        };
+
+        model_opts.to_model(&counter_opts);
+    }
+
+    /// Check that servers stay in sync, and recover what they missed, with the last server
+    /// joining late and the first leaving.
+    fn check_join_leave(sync_method: SyncMethod) {
+        let model_opts = ModelOpts {
+            servers: 3,
+            sync_method,
+            late_joiners: 1,
+            leavers: 1,
+            in_sync_check: true,
+            error_free_check: true,
+            checks: vec!["crash-recovery".to_owned()],
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+
+        let discoveries = amc_test::discoveries(model_opts, counter_opts);
+        assert!(!discoveries.contains(&"in sync when syncing is done and no in-flight requests"));
+        assert!(
+            !discoveries.contains(&"servers recover through sync when there are no messages left")
+        );
+        assert!(!discoveries.contains(&"no errors set (from panics)"));
+    }
+
+    #[test]
+    fn join_leave_changes() {
+        check_join_leave(SyncMethod::Changes);
+    }
+
+    #[test]
+    fn join_leave_messages() {
+        check_join_leave(SyncMethod::Messages);
+    }
+
+    #[test]
+    fn join_leave_save_load() {
+        check_join_leave(SyncMethod::SaveLoad);
+    }
+
+    #[test]
+    fn join_leave_incremental_save_load() {
+        check_join_leave(SyncMethod::IncrementalSaveLoad);
+    }
+
+    fn crash_opts(sync_method: SyncMethod) -> ModelOpts {
+        ModelOpts {
+            servers: 2,
+            sync_method,
+            restarts: true,
+            restart_mode: RestartMode::Crash,
+            checks: vec!["crash-recovery".to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn crash_recovery_messages() {
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+
+        // the sync protocol gets back anything that a peer received before the crash
+        let discoveries = amc_test::discoveries(crash_opts(SyncMethod::Messages), counter_opts);
+        assert!(
+            !discoveries.contains(&"servers recover through sync when there are no messages left")
+        );
+    }
+
+    #[test]
+    fn crash_recovery_changes() {
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+
+        // a change sent to a peer but not persisted is lost in a crash, and peers never send it
+        // back
+        let discoveries = amc_test::discoveries(crash_opts(SyncMethod::Changes), counter_opts);
+        assert!(
+            discoveries.contains(&"servers recover through sync when there are no messages left")
+        );
+    }
+
+    #[test]
+    fn partition_drop_recovers_on_heal() {
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::Changes,
+            partitions: true,
+            partition_mode: PartitionMode::Drop,
+            checks: vec!["crash-recovery".to_owned()],
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+
+        // changes dropped at the partition are sent again once it heals
+        let discoveries = amc_test::discoveries(model_opts, counter_opts);
+        assert!(
+            !discoveries.contains(&"servers recover through sync when there are no messages left")
+        );
+    }
+
+    #[test]
+    fn both_fixes_incremental_save_load() {
+        let counter_opts = |decrements| CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements,
+        };
+
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::IncrementalSaveLoad,
+            in_sync_check: true,
+            save_load_check: true,
+            ..Default::default()
+        };
+        assert!(amc_test::discoveries(model_opts, counter_opts(1)).is_empty());
+
+        // documents rebuilt from their chunks on restart keep syncing from where they were
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::IncrementalSaveLoad,
+            restarts: true,
+            restart_mode: RestartMode::IncrementalSaveLoad,
+            in_sync_check: true,
+            ..Default::default()
+        };
+        assert!(amc_test::discoveries(model_opts, counter_opts(0)).is_empty());
+    }
+
+    #[test]
+    fn out_of_order_changes_are_queued() {
+        let model_opts = ModelOpts {
+            servers: 2,
+            sync_method: SyncMethod::Changes,
+            out_of_order_changes: true,
+            network: NetworkType::Unordered,
+            checks: vec!["queued-changes".to_owned()],
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 2,
+            decrements: 0,
+        };
+
+        let model = model_opts.to_model(&counter_opts).property(
+            Expectation::Sometimes,
+            "a change is queued",
+            |_, state| {
+                state.actor_states.iter().any(|s| match &**s {
+                    GlobalActorState::Server(s) => s.document().has_queued_changes(),
+                    GlobalActorState::Client(_) => false,
+                })
+            },
+        );
+        let checker = model.checker().spawn_bfs().join();
+        let discoveries = checker.discoveries();
+        // a change arrives before the one it depends on, but is applied once that arrives
+        assert!(discoveries.contains_key("a change is queued"));
+        assert!(!discoveries.contains_key("no queued changes when syncing is done"));
+    }
+
+    #[test]
+    fn both_fixes_through_relay() {
+        for sync_method in [SyncMethod::Changes, SyncMethod::Messages] {
+            // the servers are only connected to the relay at the hub, which forwards between them
+            let model_opts = ModelOpts {
+                servers: 2,
+                relays: 1,
+                topology: Topology::Star,
+                sync_method,
+                in_sync_check: true,
+                ..Default::default()
+            };
+            let counter_opts = CounterOpts {
+                counter_type: true,
+                initial_change: true,
+                increments: 1,
+                decrements: 0,
+            };
+
+            let discoveries = amc_test::discoveries(model_opts, counter_opts);
+            assert!(
+                discoveries.is_empty(),
+                "{:?}: {:?}",
+                sync_method,
+                discoveries
+            );
+        }
+    }
+
+    #[test]
+    fn report_diffs_only_failing_properties() {
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+        // servers differ both in the example of this and in the counterexample to crash recovery
+        let model = crash_opts(SyncMethod::Changes)
+            .to_model(&counter_opts)
+            .property(Expectation::Sometimes, "servers differ", |_, state| {
+                !all_same_heads(&state.actor_states)
+            });
+
+        let mut reporter = Reporter::new::<CounterOpts>(&model).capture();
+        model.checker().spawn_bfs().join_and_report(&mut reporter);
+        let report = reporter.captured().unwrap();
+        let section = |name: &str| {
+            let start = report.find(&format!("{:?}", name)).unwrap();
+            let end = report[start..].find("To explore").unwrap();
+            &report[start..start + end]
+        };
+        assert!(
+            section("servers recover through sync when there are no messages left")
+                .contains("differs from actor")
+        );
+        assert!(!section("servers differ").contains("differs from actor"));
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown check \"in-snyc\"")]
+    fn unknown_check_rejected() {
+        let model_opts = ModelOpts {
+            checks: vec!["save-load".to_owned(), "in-snyc".to_owned()],
+            ..Default::default()
+        };
+        let counter_opts = CounterOpts {
+            counter_type: true,
+            initial_change: true,
+            increments: 1,
+            decrements: 0,
+        };
+
+        model_opts.to_model(&counter_opts);
+    }
 }
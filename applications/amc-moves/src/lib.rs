@@ -9,13 +9,18 @@
 use amc::application::Application;
 use amc::application::DerefDocument;
 use amc::application::Document;
+use amc::application::Scalar;
+use amc::application::Specification;
+use amc::application::Value;
 use amc::driver::ApplicationMsg;
 use amc::driver::Drive;
 use amc::global::GlobalActor;
 use amc::global::GlobalActorState;
 use amc::global::GlobalMsg;
 use amc::model::ModelBuilder;
+use amc::properties::refinement_check;
 use amc::properties::syncing_done;
+use amc::properties::Check;
 use automerge::transaction::Transactable;
 use automerge::ObjType;
 use automerge::ReadDoc;
@@ -48,8 +53,7 @@ impl Application for List {
     type Output = ();
     type State = ListState;
 
-    fn init(&self, id: usize) -> Self::State {
-        let mut doc = Document::new(id);
+    fn init(&self, _id: usize, mut doc: Document) -> Self::State {
         doc.with_initial_change(|txn| {
             let list_id = txn.put_object(ROOT, "list", ObjType::List).unwrap();
 
@@ -87,6 +91,39 @@ impl Application for List {
     }
 }
 
+/// Sequential specification of the list, moving elements around a vector.
+pub struct ListSpec;
+
+impl Specification<List> for ListSpec {
+    type State = Vec<Value>;
+
+    fn init(app: &List) -> Self::State {
+        (0..app.initial_size)
+            .map(|i| {
+                let c = char::from_u32(('a' as u32) + i as u32).unwrap();
+                Value::Scalar(Scalar::Str(c.to_string()))
+            })
+            .collect()
+    }
+
+    fn apply(state: &mut Self::State, input: &ListInput) {
+        match input {
+            ListInput::Move(from, to) => {
+                let item = state.remove(*from);
+                let to = if from < to { to - 1 } else { *to };
+                state.insert(to, item);
+            }
+        }
+    }
+
+    fn abstraction(document: &Document) -> Self::State {
+        match document.value_at(&["list"]) {
+            Some(Value::List(values)) => values,
+            _ => Vec::new(),
+        }
+    }
+}
+
 impl DerefDocument for ListState {
     fn document(&self) -> &Document {
         &self.doc
@@ -210,10 +247,10 @@ impl ModelBuilder for MovesOpts {
 
                 state.actor_states.iter().all(|s| {
                     if let GlobalActorState::Server(n) = &**s {
-                        let (_, list_id) = n.document().get(ROOT, "list").unwrap().unwrap();
-
-                        let values: Vec<_> = n.document().list_range(list_id, ..).collect();
-                        values.windows(2).all(|w| w[0].1 != w[1].1)
+                        match n.value_at(&["list"]) {
+                            Some(Value::List(values)) => values.windows(2).all(|w| w[0] != w[1]),
+                            _ => true,
+                        }
                     } else {
                         true
                     }
@@ -222,6 +259,13 @@ impl ModelBuilder for MovesOpts {
         )]
     }
 
+    fn checks(
+        &self,
+    ) -> Vec<Check<ActorModel<GlobalActor<Self::App, Self::Driver>, Self::Config, Self::History>>>
+    {
+        vec![refinement_check::<ListSpec, _, _, _, _>()]
+    }
+
     fn record_input(
         &self,
     ) -> fn(
@@ -246,7 +290,10 @@ pub struct Config {}
 
 #[cfg(test)]
 mod tests {
-    use amc::{application::server::SyncMethod, model::ModelOpts};
+    use amc::{
+        application::server::SyncMethod, model::ModelOpts, properties::with_refinement_check,
+    };
+    use stateright::{Checker, Model};
 
     use expect_test::expect;
 
@@ -257,12 +304,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let moves_opts = MovesOpts { initial_size: 1 };
 
@@ -285,12 +327,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Messages,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let moves_opts = MovesOpts { initial_size: 1 };
 
@@ -311,4 +348,62 @@ mod tests {
                 To explore this path try re-running with `explore 4596863851397185902/221331385123046224/15499083154062355532/8728495104524905156/8859108831131884740/12085236052126249328/8782270388884776451/9630948498218840186/6468371964391684142`"#]],
         );
     }
+
+    /// A specification that wrongly inserts moved elements at their target index before removing
+    /// them, so moves forwards land one place too far.
+    struct UnadjustedMovesSpec;
+
+    impl Specification<List> for UnadjustedMovesSpec {
+        type State = Vec<Value>;
+
+        fn init(app: &List) -> Self::State {
+            ListSpec::init(app)
+        }
+
+        fn apply(state: &mut Self::State, input: &ListInput) {
+            match input {
+                ListInput::Move(from, to) => {
+                    let item = state.remove(*from);
+                    state.insert((*to).min(state.len()), item);
+                }
+            }
+        }
+
+        fn abstraction(document: &Document) -> Self::State {
+            ListSpec::abstraction(document)
+        }
+    }
+
+    fn refinement_opts() -> ModelOpts {
+        ModelOpts {
+            servers: 1,
+            causal_history: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn refines_specification() {
+        let model_opts = ModelOpts {
+            checks: vec!["refinement".to_owned()],
+            ..refinement_opts()
+        };
+        let moves_opts = MovesOpts { initial_size: 3 };
+
+        let discoveries = amc_test::discoveries(model_opts, moves_opts);
+        assert!(!discoveries.contains(&"converged documents refine the specification"));
+    }
+
+    #[test]
+    fn wrong_specification_fails_refinement() {
+        let moves_opts = MovesOpts { initial_size: 3 };
+
+        let model = with_refinement_check::<UnadjustedMovesSpec, _, _, _, _>(
+            refinement_opts().to_model(&moves_opts),
+        );
+        let checker = model.checker().spawn_bfs().join();
+        assert!(checker
+            .discoveries()
+            .contains_key("converged documents refine the specification"));
+    }
 }
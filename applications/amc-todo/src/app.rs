@@ -1,8 +1,7 @@
 use amc::application::DerefDocument;
 use amc::application::Document;
+use amc::application::DocumentTransaction;
 use automerge::transaction::Transactable;
-use automerge::transaction::Transaction;
-use automerge::transaction::UnObserved;
 use automerge::ObjId;
 use automerge::ObjType;
 use automerge::ReadDoc;
@@ -47,9 +46,8 @@ impl DerefDocument for AppState {
 }
 
 impl AppState {
-    pub fn new(id: usize, random_ids: bool, initial_change: bool) -> Self {
+    pub fn new(id: usize, mut doc: Document, random_ids: bool, initial_change: bool) -> Self {
         let seed = id as u64;
-        let mut doc = Document::new(id);
         if initial_change {
             doc.with_initial_change(|tx| {
                 Self::todos_map_tx(tx);
@@ -63,7 +61,7 @@ impl AppState {
         }
     }
 
-    fn todos_map_tx(tx: &mut Transaction<UnObserved>) -> ObjId {
+    fn todos_map_tx(tx: &mut DocumentTransaction) -> ObjId {
         if let Ok(Some((_, id))) = tx.get(ROOT, "todos") {
             return id;
         }
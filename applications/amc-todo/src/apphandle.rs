@@ -1,9 +1,13 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use tracing::debug;
 
-use amc::application::Application;
+use amc::application::{Application, Document, Scalar, Specification, Value};
 
-use crate::{app::AppState, driver::AppOutput};
+use crate::{
+    app::AppState,
+    driver::{AppInput, AppOutput},
+};
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub struct App {
@@ -18,8 +22,8 @@ impl Application for App {
 
     type State = AppState;
 
-    fn init(&self, id: usize) -> Self::State {
-        AppState::new(id, self.random_ids, self.initial_change)
+    fn init(&self, id: usize, document: Document) -> Self::State {
+        AppState::new(id, document, self.random_ids, self.initial_change)
     }
 
     fn execute(&self, document: &mut Cow<Self::State>, input: Self::Input) -> Option<AppOutput> {
@@ -52,3 +56,65 @@ impl Application for App {
         Some(output)
     }
 }
+
+/// Sequential specification of the todos, with ids given out in order.
+///
+/// This doesn't hold when using random ids.
+pub struct TodoSpec;
+
+impl Specification<App> for TodoSpec {
+    /// The text and completion of each todo, by id.
+    type State = BTreeMap<u32, (String, bool)>;
+
+    fn init(_app: &App) -> Self::State {
+        BTreeMap::new()
+    }
+
+    fn apply(state: &mut Self::State, input: &AppInput) {
+        match input {
+            AppInput::CreateTodo(text) => {
+                // ids follow the last key in the document, which are ordered as strings
+                let id = state
+                    .keys()
+                    .map(u32::to_string)
+                    .max()
+                    .and_then(|id| id.parse::<u32>().ok())
+                    .map_or(1, |id| id + 1);
+                state.insert(id, (text.to_string(), false));
+            }
+            AppInput::Update(id, text) => {
+                if let Some(todo) = state.get_mut(id) {
+                    todo.0 = text.to_string();
+                }
+            }
+            AppInput::ToggleActive(id) => {
+                if let Some(todo) = state.get_mut(id) {
+                    todo.1 = true;
+                }
+            }
+            AppInput::DeleteAll => state.clear(),
+            AppInput::ListTodos => {}
+        }
+    }
+
+    fn abstraction(document: &Document) -> Self::State {
+        let todos = match document.value_at(&["todos"]) {
+            Some(Value::Map(todos)) => todos,
+            _ => return BTreeMap::new(),
+        };
+        todos
+            .into_iter()
+            .filter_map(|(id, todo)| {
+                let text = match todo.get_path(&["text"]) {
+                    Some(Value::Scalar(Scalar::Str(text))) => text.clone(),
+                    _ => String::new(),
+                };
+                let completed = matches!(
+                    todo.get_path(&["completed"]),
+                    Some(Value::Scalar(Scalar::Boolean(true)))
+                );
+                Some((id.parse().ok()?, (text, completed)))
+            })
+            .collect()
+    }
+}
@@ -1,11 +1,13 @@
 //! amc-todo shows how to implement the application side and client side with a concrete example
 
 use crate::apphandle::App;
+use crate::apphandle::TodoSpec;
 use crate::driver::AppInput;
 use crate::driver::AppOutput;
 use amc::global::GlobalActor;
 use amc::global::GlobalActorState;
 use amc::global::GlobalMsg;
+use amc::properties::refinement_check;
 use amc::properties::syncing_done;
 use clap::Parser;
 use driver::Driver;
@@ -154,6 +156,21 @@ impl amc::model::ModelBuilder for TodoOptions {
         )]
     }
 
+    fn checks(
+        &self,
+    ) -> Vec<
+        amc::properties::Check<
+            ActorModel<GlobalActor<Self::App, Self::Driver>, Self::Config, Self::History>,
+        >,
+    > {
+        if self.random_ids {
+            // ids can't be predicted by the specification
+            Vec::new()
+        } else {
+            vec![refinement_check::<TodoSpec, _, _, _, _>()]
+        }
+    }
+
     fn record_input(
         &self,
     ) -> fn(cfg: &Config, history: &AppHistory, Envelope<&GlobalMsg<App>>) -> Option<AppHistory>
@@ -189,7 +206,14 @@ impl amc::model::ModelBuilder for TodoOptions {
 
 #[cfg(test)]
 mod tests {
-    use amc::{application::server::SyncMethod, model::ModelOpts};
+    use std::collections::BTreeMap;
+
+    use amc::{
+        application::{server::SyncMethod, Document, Specification},
+        model::ModelOpts,
+        properties::with_refinement_check,
+    };
+    use stateright::{Checker, Model};
 
     use expect_test::expect;
 
@@ -200,12 +224,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let todo_opts = TodoOptions {
             random_ids: false,
@@ -233,12 +252,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let todo_opts = TodoOptions {
             random_ids: true,
@@ -266,12 +280,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let todo_opts = TodoOptions {
             random_ids: false,
@@ -300,12 +309,7 @@ mod tests {
         let model_opts = ModelOpts {
             servers: 2,
             sync_method: SyncMethod::Changes,
-            batch_synchronisation: false,
-            restarts: false,
-            in_sync_check: false,
-            save_load_check: false,
-            historical_check: false,
-            error_free_check: false,
+            ..Default::default()
         };
         let counter_opts = TodoOptions {
             random_ids: true,
@@ -322,4 +326,68 @@ mod tests {
             "#]],
         );
     }
+
+    /// A specification that wrongly gives out ids from zero.
+    struct ZeroIdsSpec;
+
+    impl Specification<App> for ZeroIdsSpec {
+        type State = BTreeMap<u32, (String, bool)>;
+
+        fn init(app: &App) -> Self::State {
+            TodoSpec::init(app)
+        }
+
+        fn apply(state: &mut Self::State, input: &AppInput) {
+            match input {
+                AppInput::CreateTodo(text) => {
+                    let id = state.keys().max().map_or(0, |id| id + 1);
+                    state.insert(id, (text.to_string(), false));
+                }
+                input => TodoSpec::apply(state, input),
+            }
+        }
+
+        fn abstraction(document: &Document) -> Self::State {
+            TodoSpec::abstraction(document)
+        }
+    }
+
+    fn refinement_opts() -> (ModelOpts, TodoOptions) {
+        let model_opts = ModelOpts {
+            servers: 1,
+            causal_history: true,
+            ..Default::default()
+        };
+        let todo_opts = TodoOptions {
+            random_ids: false,
+            initial_change: true,
+            updater: false,
+            toggler: false,
+        };
+        (model_opts, todo_opts)
+    }
+
+    #[test]
+    fn refines_specification() {
+        let (model_opts, todo_opts) = refinement_opts();
+        let model_opts = ModelOpts {
+            checks: vec!["refinement".to_owned()],
+            ..model_opts
+        };
+
+        let discoveries = amc_test::discoveries(model_opts, todo_opts);
+        assert!(!discoveries.contains(&"converged documents refine the specification"));
+    }
+
+    #[test]
+    fn wrong_specification_fails_refinement() {
+        let (model_opts, todo_opts) = refinement_opts();
+
+        let model =
+            with_refinement_check::<ZeroIdsSpec, _, _, _, _>(model_opts.to_model(&todo_opts));
+        let checker = model.checker().spawn_bfs().join();
+        assert!(checker
+            .discoveries()
+            .contains_key("converged documents refine the specification"));
+    }
 }
@@ -2,9 +2,11 @@
 
 use amc::model::{ModelBuilder, ModelOpts};
 use amc::report::TestReporter;
+use amc::symmetry::representative;
 use expect_test::Expect;
 use stateright::Checker;
 use stateright::Model;
+use std::time::Instant;
 
 /// Run the model checker using BFS and assert against the expected output of the test reporter.
 pub fn check_bfs<O>(model_opts: ModelOpts, app_opts: O, expected: Expect)
@@ -18,3 +20,81 @@ where
     model.checker().spawn_bfs().join_and_report(&mut reporter);
     expected.assert_eq(&reporter.data);
 }
+
+/// Run the model checker using BFS and get the names of the properties it discovered, sorted.
+pub fn discoveries<O>(model_opts: ModelOpts, app_opts: O) -> Vec<&'static str>
+where
+    O: ModelBuilder,
+    O::History: Sync + Send + 'static,
+    O::Config: Sync + Send,
+{
+    explore(model_opts, app_opts).1
+}
+
+/// Run the model checker using BFS, getting the number of unique states it explored and the
+/// names of the properties it discovered, sorted.
+pub fn explore<O>(model_opts: ModelOpts, app_opts: O) -> (usize, Vec<&'static str>)
+where
+    O: ModelBuilder,
+    O::History: Sync + Send + 'static,
+    O::Config: Sync + Send,
+{
+    let model = model_opts.to_model(&app_opts);
+    let checker = model.checker().spawn_bfs().join();
+    let mut discoveries = checker.discoveries().into_keys().collect::<Vec<_>>();
+    discoveries.sort();
+    (checker.unique_state_count(), discoveries)
+}
+
+/// Get the peak resident memory of this process in kilobytes, where the platform reports it.
+pub fn peak_memory_kb() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let peak = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?;
+    peak.trim().trim_end_matches("kB").trim().parse().ok()
+}
+
+/// Run the model checker using BFS with and without symmetry reduction, asserting that symmetry
+/// explores fewer states and discovers the same properties.
+///
+/// The time taken by each run and the peak memory after it are printed.
+pub fn check_symmetry<O>(model_opts: ModelOpts, app_opts: O)
+where
+    O: ModelBuilder,
+    O::History: Sync + Send + 'static,
+    O::Config: Sync + Send,
+{
+    let run = |symmetry: bool| {
+        let model_opts = ModelOpts {
+            symmetry,
+            ..model_opts.clone()
+        };
+        let model = model_opts.to_model(&app_opts);
+        let mut checker = model.checker();
+        if symmetry {
+            checker = checker.symmetry_fn(representative);
+        }
+        let start = Instant::now();
+        let checker = checker.spawn_bfs().join();
+        println!(
+            "symmetry={}: {} unique states in {:?}, peak memory {:?} kB",
+            symmetry,
+            checker.unique_state_count(),
+            start.elapsed(),
+            peak_memory_kb()
+        );
+        let mut discoveries = checker.discoveries().into_keys().collect::<Vec<_>>();
+        discoveries.sort();
+        (checker.unique_state_count(), discoveries)
+    };
+    let (full_states, full_discoveries) = run(false);
+    let (reduced_states, reduced_discoveries) = run(true);
+    assert!(
+        reduced_states < full_states,
+        "symmetry explored {} states, without it {}",
+        reduced_states,
+        full_states
+    );
+    assert_eq!(reduced_discoveries, full_discoveries);
+}
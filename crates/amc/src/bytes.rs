@@ -4,6 +4,24 @@ use std::fmt::Debug;
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub struct Bytes(pub Vec<u8>);
 
+impl Bytes {
+    /// Corrupt these bytes by truncating them to half their length.
+    pub(crate) fn truncated(mut self) -> Self {
+        let len = self.0.len() / 2;
+        self.0.truncate(len);
+        self
+    }
+
+    /// Corrupt these bytes by flipping a bit in the middle of them.
+    pub(crate) fn bit_flipped(mut self) -> Self {
+        let middle = self.0.len() / 2;
+        if let Some(byte) = self.0.get_mut(middle) {
+            *byte ^= 1;
+        }
+        self
+    }
+}
+
 impl Debug for Bytes {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use base64::Engine;
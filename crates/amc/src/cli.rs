@@ -4,9 +4,11 @@ use tracing::{debug, subscriber::set_global_default};
 use tracing_subscriber::EnvFilter;
 
 use crate::{
+    commute,
     global::GlobalActor,
     model::{ModelBuilder, ModelOpts},
     report::Reporter,
+    symmetry,
 };
 
 /// How to run the model.
@@ -28,6 +30,8 @@ pub enum Runner {
     CheckIterative,
     /// Launch a checker using breadth-first search.
     CheckBfs,
+    /// Check that pairs of inputs from different servers commute, without model checking.
+    Commute,
 }
 
 /// Arguments for running a model check.
@@ -65,6 +69,9 @@ impl RunArgs {
         let mut checker = model.clone().checker();
         checker = checker.target_max_depth(self.max_depth);
         checker = checker.threads(std::thread::available_parallelism().unwrap().get());
+        if self.model_opts.symmetry {
+            checker = checker.symmetry_fn(symmetry::representative);
+        }
         checker
     }
 
@@ -83,6 +90,30 @@ impl RunArgs {
 
         println!("{:?}", self);
         println!("{:?}", model_builder);
+
+        if let Runner::Commute = self.command {
+            let outcomes = commute::check_pairs(&model_builder, &self.model_opts);
+            let failures = outcomes.iter().filter(|o| !o.is_ok()).count();
+            for outcome in outcomes.iter().filter(|o| !o.is_ok()) {
+                println!(
+                    "Inputs {:?} on server {} and {:?} on server {}: commutes={} merge_matches_sequential={} idempotent={}",
+                    outcome.left.1,
+                    outcome.left.0,
+                    outcome.right.1,
+                    outcome.right.0,
+                    outcome.commutes,
+                    outcome.merge_matches_sequential,
+                    outcome.idempotent
+                );
+            }
+            println!(
+                "Checked {} pairs of inputs, {} with problems",
+                outcomes.len(),
+                failures
+            );
+            return;
+        }
+
         let model = self.model_opts.to_model(&model_builder);
 
         let checker = self.build_checker::<M>(&model);
@@ -149,6 +180,7 @@ impl RunArgs {
                     .spawn_bfs()
                     .join_and_report(&mut Reporter::new::<M>(&model));
             }
+            Runner::Commute => unreachable!("handled before building the model"),
         }
     }
 }
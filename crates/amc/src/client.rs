@@ -3,8 +3,10 @@ use tracing::debug;
 
 use stateright::actor::{Actor, Id};
 
+use automerge::{ChangeHash, Patch};
+
 use crate::{
-    document::Document,
+    document::{Document, Value},
     driver::Drive,
     global::{GlobalMsg, GlobalTimer},
 };
@@ -13,7 +15,7 @@ use crate::{
 /// This ensures that no sync messages are applied within the body of execution.
 pub trait Application: Clone + Hash + Eq + Debug + Send + Sync {
     /// Inputs that the application accepts to trigger behaviour.
-    type Input: Clone + Hash + Eq + Debug + Send + Sync;
+    type Input: Clone + Hash + Eq + Debug + Send + Sync + 'static;
 
     /// Outputs that the behaviour returns.
     type Output: Clone + Hash + Eq + Debug + Send + Sync;
@@ -22,11 +24,46 @@ pub trait Application: Clone + Hash + Eq + Debug + Send + Sync {
     type State: DerefDocument + Send + Sync;
 
     /// Initialise an application, performing any setup logic.
-    fn init(&self, id: usize) -> Self::State;
+    ///
+    /// The document is empty and already has the actor ids the server was configured with, so
+    /// any initial change is made with the configured actor.
+    fn init(&self, id: usize, document: Document) -> Self::State;
 
     /// Execute an application, triggering some behaviour with a given input, expecting a
     /// corresponding output.
     fn execute(&self, state: &mut Cow<Self::State>, input: Self::Input) -> Option<Self::Output>;
+
+    /// Handle patches from changes to the document, such as to keep a derived view up to date.
+    ///
+    /// This is only called when the document is observing patches, with the patches from both
+    /// local transactions and changes from peers. When `reset` is set the document was just
+    /// initialised or was replaced, such as by a restart, and the patches build it up from empty.
+    fn on_patches(&self, _state: &mut Cow<Self::State>, _reset: bool, _patches: Vec<Patch>) {}
+
+    /// Get the view derived from patches, if this application keeps one.
+    fn patched_view(&self, _state: &Self::State) -> Option<Value> {
+        None
+    }
+}
+
+/// A sequential reference model of an application: a pure state machine over its inputs.
+///
+/// Converged documents are checked to match the specification applied to some order of the
+/// inputs that respects causality.
+pub trait Specification<A: Application> {
+    /// State of the reference model.
+    ///
+    /// States are hashed to remember the partial orders that have already been tried.
+    type State: Clone + Debug + Eq + Hash;
+
+    /// The initial state, matching a document just after the application was initialised.
+    fn init(app: &A) -> Self::State;
+
+    /// Apply an input to the state.
+    fn apply(state: &mut Self::State, input: &A::Input);
+
+    /// Map a document to the state of the reference model it represents.
+    fn abstraction(document: &Document) -> Self::State;
 }
 
 /// Get access to a document.
@@ -36,6 +73,21 @@ pub trait DerefDocument: Clone + Hash + Eq + Debug {
 
     /// Get a mutable reference to the document.
     fn document_mut(&mut self) -> &mut Document;
+
+    /// Materialize the document into a JSON-like view.
+    fn materialize(&self) -> Value {
+        self.document().materialize()
+    }
+
+    /// Materialize the document into a JSON-like view as it was at the given heads.
+    fn materialize_at(&self, heads: &[ChangeHash]) -> Value {
+        self.document().materialize_at(heads)
+    }
+
+    /// Get the materialized value at the given path of keys, with list indices given as numbers.
+    fn value_at(&self, path: &[&str]) -> Option<Value> {
+        self.document().value_at(path)
+    }
 }
 
 /// Contains the input to or output from the application.
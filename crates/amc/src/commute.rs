@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+
+use automerge::ActorId;
+
+use crate::{
+    client::{Application, DerefDocument},
+    document::Document,
+    drive::Drive,
+    model::{ModelBuilder, ModelOpts},
+};
+
+/// The outcome of running a pair of inputs from different servers.
+#[derive(Debug)]
+pub struct PairOutcome<I> {
+    /// The server and input on one side.
+    pub left: (usize, I),
+    /// The server and input on the other side.
+    pub right: (usize, I),
+    /// Whether running the inputs one after the other gives the same document in either order.
+    pub commutes: bool,
+    /// Whether running the inputs concurrently and merging gives the document of one of the
+    /// sequential orders.
+    pub merge_matches_sequential: bool,
+    /// Whether running each input twice in a row gives the same document as running it once.
+    pub idempotent: bool,
+}
+
+impl<I> PairOutcome<I> {
+    /// Check whether the pair showed no problems.
+    pub fn is_ok(&self) -> bool {
+        self.commutes && self.merge_matches_sequential && self.idempotent
+    }
+}
+
+/// Run each pair of inputs that the drivers of different servers start with, comparing the
+/// documents from running them concurrently and merging against running them one after the other.
+///
+/// This only covers the inputs drivers start with, not those made in response to outputs.
+pub fn check_pairs<M: ModelBuilder>(
+    model_builder: &M,
+    model_opts: &ModelOpts,
+) -> Vec<PairOutcome<<M::App as Application>::Input>> {
+    let config = model_builder.config(model_opts);
+    let nodes = model_opts.servers + model_opts.relays;
+    // clients are numbered after the servers and relays, in the order of their servers
+    let mut client_id = nodes;
+    let inputs = (0..model_opts.servers)
+        .map(|server| {
+            let mut inputs = Vec::new();
+            for driver in model_builder.drivers(server, &config) {
+                let (_, messages) = driver.init(client_id);
+                inputs.extend(messages);
+                client_id += 1;
+            }
+            inputs
+        })
+        .collect::<Vec<_>>();
+
+    let mut outcomes = Vec::new();
+    for left in 0..model_opts.servers {
+        for right in left + 1..model_opts.servers {
+            let left_app = model_builder.application(left, &config);
+            let right_app = model_builder.application(right, &config);
+            for left_input in &inputs[left] {
+                for right_input in &inputs[right] {
+                    let left_then_right =
+                        execute(&left_app, left, model_opts, &[left_input, right_input]);
+                    let right_then_left =
+                        execute(&left_app, left, model_opts, &[right_input, left_input]);
+                    let left_twice =
+                        execute(&left_app, left, model_opts, &[left_input, left_input]);
+                    let right_twice =
+                        execute(&right_app, right, model_opts, &[right_input, right_input]);
+
+                    let mut merged = execute(&left_app, left, model_opts, &[left_input]);
+                    let other = execute(&right_app, right, model_opts, &[right_input]);
+                    let idempotent = left_twice.document().materialize()
+                        == merged.document().materialize()
+                        && right_twice.document().materialize() == other.document().materialize();
+
+                    let changes = merged
+                        .document()
+                        .get_changes_added(other.document())
+                        .into_iter()
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    for change in changes {
+                        merged.document_mut().apply_change(change);
+                    }
+                    let merged_value = merged.document().materialize();
+
+                    let left_then_right = left_then_right.document().materialize();
+                    let right_then_left = right_then_left.document().materialize();
+                    outcomes.push(PairOutcome {
+                        left: (left, left_input.clone()),
+                        right: (right, right_input.clone()),
+                        commutes: left_then_right == right_then_left,
+                        merge_matches_sequential: merged_value == left_then_right
+                            || merged_value == right_then_left,
+                        idempotent,
+                    });
+                }
+            }
+        }
+    }
+    outcomes
+}
+
+/// Run the inputs, in order, on a freshly initialised application with the actor ids its server
+/// would give it.
+fn execute<A: Application>(
+    app: &A,
+    id: usize,
+    model_opts: &ModelOpts,
+    inputs: &[&A::Input],
+) -> A::State {
+    let document = Document::with_actors(
+        model_opts.actor_ids.actor_id(id),
+        ActorId::from(model_opts.initial_change_actor.to_be_bytes()),
+    );
+    let state = app.init(id, document);
+    let mut state = Cow::Owned(state);
+    for input in inputs {
+        app.execute(&mut state, (*input).clone());
+    }
+    state.into_owned()
+}
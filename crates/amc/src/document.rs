@@ -1,31 +1,458 @@
 use std::fmt::Debug;
-use std::hash::Hash;
-use std::{collections::BTreeMap, ops::Deref};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::{Deref, RangeBounds},
+};
 
 use automerge::sync::SyncDoc;
-use automerge::transaction::{Transaction, UnObserved};
-use automerge::{sync, ActorId, Automerge, Change, ChangeHash, ReadDoc, ROOT};
+use automerge::transaction::{Observed, Transactable, Transaction, UnObserved};
+use automerge::{
+    sync, ActorId, Automerge, AutomergeError, Change, ChangeHash, Keys, KeysAt, ListRange,
+    ListRangeAt, MapRange, MapRangeAt, ObjId, ObjType, Parents, Patch, Prop, ReadDoc, ScalarValue,
+    Values, VecOpObserver, ROOT,
+};
+
+use crate::bytes::Bytes;
+use crate::history::{CausalHistory, Operation, RecordedInput};
+use crate::server::ServerMsg;
 
 /// A document that holds an automerge object and also the sync states for peers.
+///
+/// The automerge object is shared between clones, and only copied when a shared clone is
+/// modified, so cloning a document doesn't depend on the size of its history. Transactions don't
+/// copy it until they make a change, nor do syncs that bring nothing new, and saving it is done
+/// once for all clones sharing it.
 #[derive(Clone)]
 pub struct Document {
-    am: Automerge,
+    am: Arc<SharedHistory>,
     /// States for the syncing.
     sync_states: BTreeMap<usize, sync::State>,
     /// Heads of the last sync operation.
     last_sent_heads: Vec<ChangeHash>,
     /// Whether this document has encountered an error (indicates an application failure).
     error: bool,
+    /// Whether this document is part of the network.
+    membership: Membership,
+    /// Peers that have left the network, that no sync state is kept for.
+    departed_peers: BTreeSet<usize>,
+    /// Number of messages from peers that couldn't be decoded or applied.
+    invalid_messages: usize,
     /// Whether to show the json repr in debug.
     debug_materialize: bool,
+    /// How this document is hashed when deduplicating states.
+    state_hashing: StateHashing,
+    /// Actor that makes the initial change of the document.
+    initial_change_actor: ActorId,
+    /// Patches from changes to the document not yet delivered, `None` if not observing.
+    ///
+    /// These are derived from the document so aren't part of its identity.
+    patches: Option<Vec<Patch>>,
+    /// Heads of the last persisted snapshot, `None` if persistence isn't being tracked.
+    persisted_heads: Option<Vec<ChangeHash>>,
+    /// Sync states persisted along with the document, restored with it after a crash.
+    persisted_sync_states: BTreeMap<usize, sync::State>,
+    /// Snapshots of sync states for peers, that they can be rolled back to.
+    sync_state_snapshots: BTreeMap<usize, sync::State>,
+    /// Messages held while partitioned from peers, `None` when not partitioned.
+    partition: Option<HeldMessages>,
+    /// Messages from peers on a lossy network, waiting to be processed or lost.
+    lossy_inbound: Vec<(usize, ServerMsg)>,
+    /// Last message with a payload sent to each peer, kept by faulty peers to replay.
+    sent_messages: BTreeMap<usize, ServerMsg>,
+    /// Progress made by sync messages, `None` if progress isn't being tracked.
+    sync_progress: Option<SyncProgress>,
+    /// Inputs processed with this document, `None` if they aren't being recorded.
+    ///
+    /// This is kept over restarts as it records what applications observed.
+    causal_history: Option<CausalHistory>,
+}
+
+/// Most rewrites to keep with each history, enough for every order of three servers.
+///
+/// Each is a whole copy of the history, so keeping one for every order of many servers would
+/// multiply the memory used.
+const MAX_CACHED_REWRITES: usize = 6;
+
+/// An automerge object shared between clones of a document, along with its saved form.
+struct SharedHistory {
+    am: Automerge,
+    /// The saved bytes of the automerge object, once it has been saved.
+    saved: OnceLock<Vec<u8>>,
+    /// The latest rewrites of the history for symmetry reduction, along with the mapping of ids
+    /// used, at most [`MAX_CACHED_REWRITES`] of them.
+    rewrites: Mutex<VecDeque<(Vec<usize>, RewrittenHistory)>>,
+    /// A spare copy of the automerge object, for receiving sync messages that don't change it
+    /// without copying it for each of them.
+    spare: Mutex<Option<Automerge>>,
+}
+
+impl SharedHistory {
+    fn new(am: Automerge) -> Arc<Self> {
+        Arc::new(Self {
+            am,
+            saved: OnceLock::new(),
+            rewrites: Mutex::new(VecDeque::new()),
+            spare: Mutex::new(None),
+        })
+    }
+
+    /// Get mutable access to the automerge object, copying it first if it is shared with other
+    /// clones, and forgetting what was derived from it as it won't match.
+    fn make_mut(this: &mut Arc<Self>) -> &mut Automerge {
+        let history = Arc::make_mut(this);
+        history.saved = OnceLock::new();
+        history.rewrites = Mutex::new(VecDeque::new());
+        history.spare = Mutex::new(None);
+        &mut history.am
+    }
+}
+
+impl Clone for SharedHistory {
+    fn clone(&self) -> Self {
+        // clones are only made to be changed so what is derived from the history would be
+        // thrown away
+        Self {
+            am: self.am.clone(),
+            saved: OnceLock::new(),
+            rewrites: Mutex::new(VecDeque::new()),
+            spare: Mutex::new(None),
+        }
+    }
+}
+
+impl Deref for SharedHistory {
+    type Target = Automerge;
+
+    fn deref(&self) -> &Self::Target {
+        &self.am
+    }
+}
+
+/// Count of sync messages received from each peer without the document changing.
+///
+/// Counting per peer keeps the rounds of syncing with different peers in a mesh from adding up.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SyncProgress {
+    /// Sync messages received from each peer since the heads last changed, saturating at the
+    /// bound.
+    unproductive: BTreeMap<usize, usize>,
+    /// Number of unproductive sync messages at which syncing is considered livelocked.
+    bound: usize,
+}
+
+/// Differences between two documents.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocumentDiff {
+    /// Changes only in the first document.
+    pub only_left: Vec<ChangeHash>,
+    /// Changes only in the second document.
+    pub only_right: Vec<ChangeHash>,
+    /// Places where the materialized documents differ.
+    pub values: Vec<ValueDiff>,
+}
+
+/// A place where two materialized documents differ.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValueDiff {
+    /// Path of keys to the value, with list indices given as numbers.
+    pub path: Vec<String>,
+    /// Value in the first document, `None` if it is missing.
+    pub left: Option<Value>,
+    /// Value in the second document, `None` if it is missing.
+    pub right: Option<Value>,
+}
+
+impl DocumentDiff {
+    /// Check whether the documents were the same.
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty() && self.only_right.is_empty() && self.values.is_empty()
+    }
+}
+
+impl std::fmt::Display for DocumentDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for hash in &self.only_left {
+            writeln!(f, "- change {}", hex::encode(hash.0))?;
+        }
+        for hash in &self.only_right {
+            writeln!(f, "+ change {}", hex::encode(hash.0))?;
+        }
+        for value in &self.values {
+            writeln!(
+                f,
+                "  /{}: {:?} != {:?}",
+                value.path.join("/"),
+                value.left,
+                value.right
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn diff_values(
+    path: &mut Vec<String>,
+    left: Option<&Value>,
+    right: Option<&Value>,
+    diffs: &mut Vec<ValueDiff>,
+) {
+    match (left, right) {
+        (Some(Value::Map(l)), Some(Value::Map(r)))
+        | (Some(Value::Table(l)), Some(Value::Table(r))) => {
+            let keys = l.keys().chain(r.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                path.push(key.clone());
+                diff_values(path, l.get(key), r.get(key), diffs);
+                path.pop();
+            }
+        }
+        (Some(Value::List(l)), Some(Value::List(r))) => {
+            for i in 0..l.len().max(r.len()) {
+                path.push(i.to_string());
+                diff_values(path, l.get(i), r.get(i), diffs);
+                path.pop();
+            }
+        }
+        (l, r) => {
+            if l != r {
+                diffs.push(ValueDiff {
+                    path: path.clone(),
+                    left: l.cloned(),
+                    right: r.cloned(),
+                });
+            }
+        }
+    }
+}
+
+/// The default actor that makes the initial change of documents.
+const INITIAL_CHANGE_ACTOR: u64 = 999;
+
+/// How servers pick the actor ids for their documents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum ActorIds {
+    /// Use the index of the server.
+    Index,
+    /// Use a random-looking 16 byte id, like a UUID, derived from the index of the server so that
+    /// runs are reproducible.
+    Uuid,
+    /// Use the same actor id for every server, as happens when users copy a profile.
+    Shared,
+}
+
+impl ActorIds {
+    /// Get the actor id for the server with the given index.
+    pub fn actor_id(&self, id: usize) -> ActorId {
+        match self {
+            ActorIds::Index => ActorId::from(id.to_be_bytes()),
+            ActorIds::Uuid => {
+                let mut bytes = Vec::with_capacity(16);
+                for half in 0..2 {
+                    let seed = ((id as u64) << 1) | half;
+                    bytes.extend_from_slice(&splitmix64(seed).to_be_bytes());
+                }
+                ActorId::from(bytes)
+            }
+            ActorIds::Shared => ActorId::from(0usize.to_be_bytes()),
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+/// Mix the bits of a value with the finaliser of splitmix64, so that nearby values give unrelated
+/// results that stay the same across platforms and releases.
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// How documents are hashed when deduplicating states in the model.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum StateHashing {
+    /// Hash the exact history and sync state, so documents are only the same if they have the same
+    /// changes.
+    Exact,
+    /// Hash the materialized content and a summary of what is left to sync.
+    ///
+    /// Documents that differ only in actor ids or the order of their changes are treated as the
+    /// same, shrinking the state space at the cost of possibly missing bugs that depend on those
+    /// differences.
+    Semantic,
+}
+
+/// Whether a document is part of the network of peers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Membership {
+    /// Yet to join the network.
+    Joining,
+    /// Part of the network.
+    Joined,
+    /// Left the network for good.
+    Left,
+}
+
+/// Messages that were held back from crossing a partition.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct HeldMessages {
+    /// Messages to be sent to peers.
+    pub(crate) outbound: Vec<(usize, ServerMsg)>,
+    /// Messages received from peers.
+    pub(crate) inbound: Vec<(usize, ServerMsg)>,
+    /// Heads last sent to peers when the partition started.
+    pub(crate) sent_heads: Vec<ChangeHash>,
+    /// Whether any messages were dropped rather than held.
+    pub(crate) dropped: bool,
+}
+
+/// A materialized value from a document, keeping the winning value for each key or index.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Value {
+    /// A map object.
     Map(BTreeMap<String, Value>),
+    /// A table object.
+    Table(BTreeMap<String, Value>),
+    /// A list object.
     List(Vec<Value>),
+    /// A text object.
     Text(String),
-    Scalar(String),
+    /// A scalar value.
+    Scalar(Scalar),
+}
+
+/// A materialized scalar, keeping the type it was stored with.
+///
+/// Floats are compared by their bits, matching how they are hashed.
+#[derive(Clone, Debug)]
+pub enum Scalar {
+    /// A byte array.
+    Bytes(Vec<u8>),
+    /// A string.
+    Str(String),
+    /// A signed integer.
+    Int(i64),
+    /// An unsigned integer.
+    Uint(u64),
+    /// A float.
+    F64(f64),
+    /// A counter with its current value, after all increments.
+    Counter(i64),
+    /// A timestamp.
+    Timestamp(i64),
+    /// A boolean.
+    Boolean(bool),
+    /// A value with a type automerge doesn't know about.
+    Unknown {
+        /// The code of the unknown type.
+        type_code: u8,
+        /// The raw bytes of the value.
+        bytes: Vec<u8>,
+    },
+    /// The null value.
+    Null,
+}
+
+impl Value {
+    /// Look up the value at the given path of keys, with list indices given as numbers.
+    ///
+    /// Returns `None` if the path doesn't lead to a value.
+    pub fn get_path(&self, path: &[&str]) -> Option<&Value> {
+        let mut value = self;
+        for key in path {
+            value = match value {
+                Value::Map(map) | Value::Table(map) => map.get(*key)?,
+                Value::List(list) => list.get(key.parse::<usize>().ok()?)?,
+                Value::Text(_) | Value::Scalar(_) => return None,
+            };
+        }
+        Some(value)
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Map(map) | Value::Table(map) => map.hash(state),
+            Value::List(list) => list.hash(state),
+            Value::Text(text) => text.hash(state),
+            Value::Scalar(scalar) => scalar.hash(state),
+        }
+    }
+}
+
+impl PartialEq for Scalar {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Scalar::Bytes(a), Scalar::Bytes(b)) => a == b,
+            (Scalar::Str(a), Scalar::Str(b)) => a == b,
+            (Scalar::Int(a), Scalar::Int(b)) => a == b,
+            (Scalar::Uint(a), Scalar::Uint(b)) => a == b,
+            // compare by bits to agree with the hash, so NaN equals itself
+            (Scalar::F64(a), Scalar::F64(b)) => a.to_bits() == b.to_bits(),
+            (Scalar::Counter(a), Scalar::Counter(b)) => a == b,
+            (Scalar::Timestamp(a), Scalar::Timestamp(b)) => a == b,
+            (Scalar::Boolean(a), Scalar::Boolean(b)) => a == b,
+            (
+                Scalar::Unknown {
+                    type_code: a_type_code,
+                    bytes: a_bytes,
+                },
+                Scalar::Unknown {
+                    type_code: b_type_code,
+                    bytes: b_bytes,
+                },
+            ) => a_type_code == b_type_code && a_bytes == b_bytes,
+            (Scalar::Null, Scalar::Null) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Scalar {}
+
+impl Hash for Scalar {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Scalar::Bytes(b) => b.hash(state),
+            Scalar::Str(s) => s.hash(state),
+            Scalar::Int(i) => i.hash(state),
+            Scalar::Uint(u) => u.hash(state),
+            // hash by bits as floats don't implement hash themselves
+            Scalar::F64(f) => f.to_bits().hash(state),
+            Scalar::Counter(c) => c.hash(state),
+            Scalar::Timestamp(t) => t.hash(state),
+            Scalar::Boolean(b) => b.hash(state),
+            Scalar::Unknown { type_code, bytes } => {
+                type_code.hash(state);
+                bytes.hash(state);
+            }
+            Scalar::Null => {}
+        }
+    }
+}
+
+impl From<&automerge::ScalarValue> for Scalar {
+    fn from(s: &automerge::ScalarValue) -> Self {
+        match s {
+            automerge::ScalarValue::Bytes(b) => Self::Bytes(b.clone()),
+            automerge::ScalarValue::Str(s) => Self::Str(s.to_string()),
+            automerge::ScalarValue::Int(i) => Self::Int(*i),
+            automerge::ScalarValue::Uint(u) => Self::Uint(*u),
+            automerge::ScalarValue::F64(f) => Self::F64(*f),
+            automerge::ScalarValue::Counter(c) => Self::Counter(i64::from(c)),
+            automerge::ScalarValue::Timestamp(t) => Self::Timestamp(*t),
+            automerge::ScalarValue::Boolean(b) => Self::Boolean(*b),
+            automerge::ScalarValue::Unknown { type_code, bytes } => Self::Unknown {
+                type_code: *type_code,
+                bytes: bytes.clone(),
+            },
+            automerge::ScalarValue::Null => Self::Null,
+        }
+    }
 }
 
 fn materialize_root(am: &Automerge) -> Value {
@@ -54,6 +481,13 @@ fn materialize_value(am: &Automerge, value: automerge::Value, id: automerge::Obj
                 }
                 Value::Map(map)
             }
+            automerge::ObjType::Table => {
+                let mut table = BTreeMap::new();
+                for (k, v, id) in am.map_range(id, ..) {
+                    table.insert(k.to_owned(), materialize_value(am, v, id));
+                }
+                Value::Table(table)
+            }
             automerge::ObjType::List => {
                 let mut list = Vec::new();
                 for (_, v, id) in am.list_range(id, ..) {
@@ -65,9 +499,8 @@ fn materialize_value(am: &Automerge, value: automerge::Value, id: automerge::Obj
                 let text = am.text(id).unwrap();
                 Value::Text(text)
             }
-            automerge::ObjType::Table => todo!(),
         },
-        automerge::Value::Scalar(s) => Value::Scalar(s.to_string()),
+        automerge::Value::Scalar(s) => Value::Scalar(Scalar::from(s.as_ref())),
     }
 }
 
@@ -86,6 +519,13 @@ fn materialize_value_at(
                 }
                 Value::Map(map)
             }
+            automerge::ObjType::Table => {
+                let mut table = BTreeMap::new();
+                for (k, v, id) in am.map_range_at(id, .., heads) {
+                    table.insert(k.to_owned(), materialize_value_at(am, v, id, heads));
+                }
+                Value::Table(table)
+            }
             automerge::ObjType::List => {
                 let mut list = Vec::new();
                 for (_, v, id) in am.list_range_at(id, .., heads) {
@@ -97,20 +537,96 @@ fn materialize_value_at(
                 let text = am.text_at(id, heads).unwrap();
                 Value::Text(text)
             }
-            automerge::ObjType::Table => todo!(),
         },
-        automerge::Value::Scalar(s) => Value::Scalar(s.to_string()),
+        automerge::Value::Scalar(s) => Value::Scalar(Scalar::from(s.as_ref())),
     }
 }
 
+/// A materialized value that keeps every conflicting value, rather than just the winner.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConflictValue {
+    /// A map with the conflicting values for each key.
+    Map(BTreeMap<String, Conflicts>),
+    /// A table with the conflicting values for each row.
+    Table(BTreeMap<String, Conflicts>),
+    /// A list with the conflicting values for each index.
+    List(Vec<Conflicts>),
+    /// A text object.
+    Text(String),
+    /// A scalar value.
+    Scalar(Scalar),
+}
+
+/// The values set concurrently for a single key or index, keyed by the id of the op that set them.
+pub type Conflicts = BTreeMap<String, ConflictValue>;
+
+fn materialize_conflicts_value(
+    am: &Automerge,
+    value: automerge::Value,
+    id: automerge::ObjId,
+) -> ConflictValue {
+    match value {
+        automerge::Value::Object(o) => match o {
+            automerge::ObjType::Map => {
+                let mut map = BTreeMap::new();
+                for k in am.keys(&id) {
+                    let conflicts = materialize_conflicts_prop(am, &id, k.as_str());
+                    map.insert(k, conflicts);
+                }
+                ConflictValue::Map(map)
+            }
+            automerge::ObjType::Table => {
+                let mut table = BTreeMap::new();
+                for k in am.keys(&id) {
+                    let conflicts = materialize_conflicts_prop(am, &id, k.as_str());
+                    table.insert(k, conflicts);
+                }
+                ConflictValue::Table(table)
+            }
+            automerge::ObjType::List => {
+                let mut list = Vec::new();
+                for i in 0..am.length(&id) {
+                    list.push(materialize_conflicts_prop(am, &id, i));
+                }
+                ConflictValue::List(list)
+            }
+            automerge::ObjType::Text => {
+                let text = am.text(id).unwrap();
+                ConflictValue::Text(text)
+            }
+        },
+        automerge::Value::Scalar(s) => ConflictValue::Scalar(Scalar::from(s.as_ref())),
+    }
+}
+
+fn materialize_conflicts_prop<P: Into<automerge::Prop>>(
+    am: &Automerge,
+    obj: &automerge::ObjId,
+    prop: P,
+) -> Conflicts {
+    let mut conflicts = BTreeMap::new();
+    for (v, id) in am.get_all(obj, prop).unwrap() {
+        conflicts.insert(id.to_string(), materialize_conflicts_value(am, v, id));
+    }
+    conflicts
+}
+
+/// Materialize the document, keeping the winning value for each key or index.
 pub fn materialize(am: &Automerge) -> Value {
     materialize_root(am)
 }
 
+/// Materialize the document as it was at the given heads.
 pub fn materialize_at(am: &Automerge, heads: &[ChangeHash]) -> Value {
     materialize_root_at(am, heads)
 }
 
+/// Materialize the document, keeping every conflicting value along with the id of the op that
+/// set it.
+pub fn materialize_conflicts(am: &Automerge) -> ConflictValue {
+    materialize_conflicts_value(am, automerge::Value::Object(automerge::ObjType::Map), ROOT)
+}
+
 impl Debug for Document {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut s = f.debug_struct("Document");
@@ -124,16 +640,31 @@ impl Debug for Document {
             .iter()
             .map(|h| hex::encode(h.0))
             .collect::<Vec<_>>();
+        let persisted_heads = self
+            .persisted_heads
+            .as_ref()
+            .map(|heads| heads.iter().map(|h| hex::encode(h.0)).collect::<Vec<_>>());
         if self.debug_materialize {
             let v = materialize(&self.am);
             s.field("doc", &v);
         } else {
-            s.field("am", &self.am);
+            s.field("am", &self.am.am);
         }
         s.field("heads", &heads)
             .field("sync_states", &self.sync_states)
             .field("last_sent_heads", &last_sent_heads)
-            .field("error", &self.error);
+            .field("error", &self.error)
+            .field("membership", &self.membership)
+            .field("departed_peers", &self.departed_peers)
+            .field("invalid_messages", &self.invalid_messages)
+            .field("persisted_heads", &persisted_heads)
+            .field("persisted_sync_states", &self.persisted_sync_states)
+            .field("sync_state_snapshots", &self.sync_state_snapshots)
+            .field("partition", &self.partition)
+            .field("lossy_inbound", &self.lossy_inbound)
+            .field("sent_messages", &self.sent_messages)
+            .field("sync_progress", &self.sync_progress)
+            .field("causal_history", &self.causal_history);
         s.finish()
     }
 }
@@ -144,6 +675,17 @@ impl PartialEq for Document {
             && self.sync_states == other.sync_states
             && self.last_sent_heads == other.last_sent_heads
             && self.error == other.error
+            && self.membership == other.membership
+            && self.departed_peers == other.departed_peers
+            && self.invalid_messages == other.invalid_messages
+            && self.persisted_heads == other.persisted_heads
+            && self.persisted_sync_states == other.persisted_sync_states
+            && self.sync_state_snapshots == other.sync_state_snapshots
+            && self.partition == other.partition
+            && self.lossy_inbound == other.lossy_inbound
+            && self.sent_messages == other.sent_messages
+            && self.sync_progress == other.sync_progress
+            && self.causal_history == other.causal_history
     }
 }
 
@@ -151,41 +693,177 @@ impl Eq for Document {}
 
 impl Hash for Document {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        if self.state_hashing == StateHashing::Semantic {
+            self.hash_semantic(state);
+            return;
+        }
         self.am.get_heads().hash(state);
         self.sync_states.hash(state);
         self.last_sent_heads.hash(state);
         self.error.hash(state);
+        self.membership.hash(state);
+        self.departed_peers.hash(state);
+        self.invalid_messages.hash(state);
+        self.persisted_heads.hash(state);
+        self.persisted_sync_states.hash(state);
+        self.sync_state_snapshots.hash(state);
+        self.partition.hash(state);
+        self.lossy_inbound.hash(state);
+        self.sent_messages.hash(state);
+        self.sync_progress.hash(state);
+        self.causal_history.hash(state);
     }
 }
 
 impl Document {
+    /// Hash the materialized content of the document along with a summary of the syncing still to
+    /// do, leaving out anything that depends on actor ids or the order of changes.
+    fn hash_semantic<H: std::hash::Hasher>(&self, state: &mut H) {
+        materialize(&self.am).hash(state);
+        // changes waiting on their dependencies aren't part of the materialized content
+        let mut missing_deps = self.am.get_missing_deps(&[]);
+        missing_deps.sort();
+        missing_deps.hash(state);
+        // which peers we are part way through syncing with
+        self.sync_states.keys().collect::<Vec<_>>().hash(state);
+        // how many changes we have that haven't been sent out yet
+        self.am
+            .get_changes(&self.last_sent_heads)
+            .map(|changes| changes.len())
+            .unwrap_or_default()
+            .hash(state);
+        self.error.hash(state);
+        self.membership.hash(state);
+        self.departed_peers.hash(state);
+        self.invalid_messages.hash(state);
+        self.persisted_heads
+            .as_ref()
+            .map(|heads| materialize_at(&self.am, heads))
+            .hash(state);
+        // collected so the peers of each are length prefixed, keeping them apart
+        self.persisted_sync_states
+            .keys()
+            .collect::<Vec<_>>()
+            .hash(state);
+        self.sync_state_snapshots
+            .keys()
+            .collect::<Vec<_>>()
+            .hash(state);
+        self.partition
+            .as_ref()
+            .map(|partition| {
+                (
+                    partition.outbound.len(),
+                    partition.inbound.len(),
+                    partition.dropped,
+                )
+            })
+            .hash(state);
+        self.lossy_inbound.len().hash(state);
+        self.sent_messages.len().hash(state);
+        self.sync_progress.hash(state);
+        self.causal_history
+            .as_ref()
+            .map(|causal_history| causal_history.operations().len())
+            .hash(state);
+    }
+
     /// Create a new document.
     pub fn new(id: usize) -> Self {
+        Self::with_actors(
+            ActorId::from(id.to_be_bytes()),
+            ActorId::from(INITIAL_CHANGE_ACTOR.to_be_bytes()),
+        )
+    }
+
+    /// Create a new document with the given actor, and actor for its initial change.
+    pub fn with_actors(actor: ActorId, initial_change_actor: ActorId) -> Self {
         let mut doc = Automerge::new();
-        doc.set_actor(ActorId::from(id.to_be_bytes()));
+        doc.set_actor(actor);
         Self {
-            am: doc,
+            am: SharedHistory::new(doc),
             sync_states: BTreeMap::new(),
             last_sent_heads: Vec::new(),
             error: false,
+            membership: Membership::Joined,
+            departed_peers: BTreeSet::new(),
+            invalid_messages: 0,
             debug_materialize: true,
+            state_hashing: StateHashing::Exact,
+            initial_change_actor,
+            patches: None,
+            persisted_heads: None,
+            persisted_sync_states: BTreeMap::new(),
+            sync_state_snapshots: BTreeMap::new(),
+            partition: None,
+            lossy_inbound: Vec::new(),
+            sent_messages: BTreeMap::new(),
+            sync_progress: None,
+            causal_history: None,
         }
     }
 
+    /// Get mutable access to the automerge object, copying it first if it is shared with other
+    /// clones.
+    fn am_mut(&mut self) -> &mut Automerge {
+        SharedHistory::make_mut(&mut self.am)
+    }
+
     /// Create an initial change for the document.
     ///
     /// This ensures that, when syncing, documents have a common root to merge from.
-    pub fn with_initial_change<F: Fn(&mut Transaction<'_, UnObserved>)>(&mut self, make_change: F) {
+    pub fn with_initial_change<F: Fn(&mut DocumentTransaction<'_>)>(&mut self, make_change: F) {
         assert!(self.am.get_changes(&[]).unwrap().is_empty());
 
         let actor = self.get_actor().clone();
-        self.am.set_actor(ActorId::from(999u64.to_be_bytes()));
+        let initial_change_actor = self.initial_change_actor.clone();
+        self.am_mut().set_actor(initial_change_actor);
 
         let mut txn = self.transaction();
         make_change(&mut txn);
         txn.commit();
 
-        self.am.set_actor(actor);
+        self.am_mut().set_actor(actor);
+    }
+
+    /// Compare this document with another, listing the changes only on one side and where their
+    /// materialized values differ.
+    pub fn diff(&self, other: &Document) -> DocumentDiff {
+        let hashes = |changes: Vec<&Change>| changes.into_iter().map(|c| c.hash()).collect();
+        let mut values = Vec::new();
+        diff_values(
+            &mut Vec::new(),
+            Some(&self.materialize()),
+            Some(&other.materialize()),
+            &mut values,
+        );
+        DocumentDiff {
+            only_left: hashes(other.am.get_changes_added(&self.am)),
+            only_right: hashes(self.am.get_changes_added(&other.am)),
+            values,
+        }
+    }
+
+    /// Materialize the document into a JSON-like view.
+    pub fn materialize(&self) -> Value {
+        materialize(&self.am)
+    }
+
+    /// Materialize the document into a JSON-like view as it was at the given heads.
+    pub fn materialize_at(&self, heads: &[ChangeHash]) -> Value {
+        materialize_at(&self.am, heads)
+    }
+
+    /// Get the materialized value at the given path of keys, with list indices given as numbers.
+    ///
+    /// For example `doc.value_at(&["todos", "1", "text"])`.
+    pub fn value_at(&self, path: &[&str]) -> Option<Value> {
+        self.materialize().get_path(path).cloned()
+    }
+
+    /// Set how this document is hashed when deduplicating states.
+    pub(crate) fn set_state_hashing(&mut self, state_hashing: StateHashing) {
+        self.state_hashing = state_hashing;
     }
 
     /// Check whether this document has errored.
@@ -198,23 +876,317 @@ impl Document {
         self.error = true;
     }
 
+    /// Check whether this document is part of the network, having joined and not left.
+    pub fn is_joined(&self) -> bool {
+        self.membership == Membership::Joined
+    }
+
+    /// Check whether this document has left the network.
+    pub fn has_left(&self) -> bool {
+        self.membership == Membership::Left
+    }
+
+    /// Set whether this document is part of the network.
+    pub(crate) fn set_membership(&mut self, membership: Membership) {
+        self.membership = membership;
+    }
+
+    /// Record that a peer has left the network, forgetting the sync state for it.
+    ///
+    /// No sync state is kept for the peer from then on, so nothing is synced with it.
+    pub(crate) fn set_departed(&mut self, peer: usize) {
+        self.departed_peers.insert(peer);
+        self.sync_states.remove(&peer);
+        self.assert_departed_forgotten();
+    }
+
+    /// Check whether a peer has left the network.
+    pub fn has_departed(&self, peer: usize) -> bool {
+        self.departed_peers.contains(&peer)
+    }
+
+    /// Assert that no sync state is kept for peers that have left.
+    fn assert_departed_forgotten(&self) {
+        assert!(
+            self.departed_peers
+                .iter()
+                .all(|peer| !self.sync_states.contains_key(peer)),
+            "sync state kept for a departed peer"
+        );
+    }
+
+    /// Get the number of messages from peers that this document couldn't decode or apply.
+    pub fn invalid_messages(&self) -> usize {
+        self.invalid_messages
+    }
+
+    /// Record that a message from a peer couldn't be decoded or applied.
+    pub fn record_invalid_message(&mut self) {
+        self.invalid_messages += 1;
+    }
+
+    /// Start tracking whether sync messages make progress, considering syncing livelocked after
+    /// `bound` sync messages in a row that don't change the document.
+    pub(crate) fn track_sync_progress(&mut self, bound: usize) {
+        self.sync_progress = Some(SyncProgress {
+            unproductive: BTreeMap::new(),
+            bound,
+        });
+    }
+
+    /// Record whether a sync message from a peer changed the document.
+    ///
+    /// A change gives every peer something new to sync, so starts counting afresh for all of them.
+    pub(crate) fn record_sync_progress(&mut self, peer: usize, changed: bool) {
+        if let Some(progress) = &mut self.sync_progress {
+            if changed {
+                progress.unproductive.clear();
+            } else {
+                let unproductive = progress.unproductive.entry(peer).or_default();
+                *unproductive = (*unproductive + 1).min(progress.bound);
+            }
+        }
+    }
+
+    /// Record a local change to the document, which starts a new round of syncing with all peers.
+    pub(crate) fn reset_sync_progress(&mut self) {
+        if let Some(progress) = &mut self.sync_progress {
+            progress.unproductive.clear();
+        }
+    }
+
+    /// Check whether this document has received too many sync messages in a row from a peer
+    /// without them changing it.
+    ///
+    /// Always false unless sync progress is being tracked.
+    pub fn is_sync_livelocked(&self) -> bool {
+        self.sync_progress.as_ref().map_or(false, |progress| {
+            progress
+                .unproductive
+                .values()
+                .any(|unproductive| *unproductive >= progress.bound)
+        })
+    }
+
+    /// Start recording the inputs processed with this document.
+    pub(crate) fn record_causal_history(&mut self) {
+        self.causal_history = Some(CausalHistory::default());
+    }
+
+    /// Get the inputs processed with this document, if they are being recorded.
+    pub fn causal_history(&self) -> Option<&CausalHistory> {
+        self.causal_history.as_ref()
+    }
+
+    /// Record an input having been processed, making the changes since `heads_before`.
+    pub(crate) fn record_operation(&mut self, input: RecordedInput, heads_before: &[ChangeHash]) {
+        if let Some(causal_history) = &mut self.causal_history {
+            let changes = |heads: &[ChangeHash]| self.am.get_changes(heads).unwrap_or_default();
+            causal_history.record(Operation {
+                input,
+                produced: changes(heads_before).iter().map(|c| c.hash()).collect(),
+                visible: changes(&[]).iter().map(|c| c.hash()).collect(),
+            });
+        }
+    }
+
+    /// Persist the document along with its sync states, the current heads and sync states are
+    /// what will be restored after a crash.
+    pub(crate) fn persist(&mut self) {
+        self.persisted_heads = Some(self.am.get_heads());
+        self.persisted_sync_states = self.sync_states.clone();
+    }
+
+    /// Roll the document back to the last persisted snapshot, losing anything since.
+    ///
+    /// The sync states are rolled back too, so they don't claim to share heads that the document
+    /// no longer has.
+    pub(crate) fn rollback_to_persisted(&mut self) {
+        let departed_peers = &self.departed_peers;
+        self.sync_states = self.persisted_sync_states.clone();
+        self.sync_states
+            .retain(|peer, _| !departed_peers.contains(peer));
+        self.assert_departed_forgotten();
+        let heads = self.persisted_heads.clone().unwrap_or_default();
+        let actor = self.am.get_actor().clone();
+        let mut am = self.am.fork_at(&heads).unwrap();
+        am.set_actor(actor);
+        self.am = SharedHistory::new(am);
+        // we can't have sent heads that we no longer have
+        let am = &self.am;
+        self.last_sent_heads
+            .retain(|h| am.get_change_by_hash(h).is_some());
+    }
+
+    /// Check whether this document is partitioned from its peers.
+    pub fn is_partitioned(&self) -> bool {
+        self.partition.is_some()
+    }
+
+    /// Partition this document from its peers.
+    pub(crate) fn partition(&mut self) {
+        let sent_heads = &self.last_sent_heads;
+        self.partition.get_or_insert_with(|| HeldMessages {
+            sent_heads: sent_heads.clone(),
+            ..Default::default()
+        });
+    }
+
+    /// Hold a message to a peer until the partition heals.
+    pub(crate) fn hold_outbound(&mut self, peer: usize, msg: ServerMsg) {
+        if let Some(partition) = &mut self.partition {
+            partition.outbound.push((peer, msg));
+        }
+    }
+
+    /// Hold a message from a peer until the partition heals.
+    pub(crate) fn hold_inbound(&mut self, peer: usize, msg: ServerMsg) {
+        if let Some(partition) = &mut self.partition {
+            partition.inbound.push((peer, msg));
+        }
+    }
+
+    /// Record that a message to or from a peer was dropped at the partition.
+    pub(crate) fn drop_message(&mut self) {
+        if let Some(partition) = &mut self.partition {
+            partition.dropped = true;
+        }
+    }
+
+    /// Go back to having last sent the given heads, so the changes since are sent again.
+    ///
+    /// Heads that the document no longer has, such as after a crash, are skipped.
+    pub(crate) fn rewind_last_sent_heads(&mut self, heads: &[ChangeHash]) {
+        let am = &self.am;
+        self.last_sent_heads = heads
+            .iter()
+            .filter(|h| am.get_change_by_hash(h).is_some())
+            .copied()
+            .collect();
+    }
+
+    /// Heal the partition, returning the messages that were held.
+    pub(crate) fn heal(&mut self) -> HeldMessages {
+        self.partition.take().unwrap_or_default()
+    }
+
+    /// Hold a message from a peer on a lossy network until it is processed or lost.
+    pub(crate) fn receive_lossy(&mut self, peer: usize, msg: ServerMsg) {
+        self.lossy_inbound.push((peer, msg));
+    }
+
+    /// Take the oldest message held from a peer on a lossy network.
+    pub(crate) fn take_lossy(&mut self, peer: usize) -> Option<ServerMsg> {
+        let index = self.lossy_inbound.iter().position(|(p, _)| *p == peer)?;
+        Some(self.lossy_inbound.remove(index).1)
+    }
+
+    /// Check whether any messages from a peer on a lossy network are held.
+    pub(crate) fn has_lossy(&self, peer: usize) -> bool {
+        self.lossy_inbound.iter().any(|(p, _)| *p == peer)
+    }
+
+    /// Check whether any messages from peers on a lossy network are held, waiting to be processed
+    /// or lost.
+    pub fn has_lossy_messages(&self) -> bool {
+        !self.lossy_inbound.is_empty()
+    }
+
+    /// Record the last message with a payload sent to a peer, returning the one sent before it.
+    pub(crate) fn replace_sent_message(
+        &mut self,
+        peer: usize,
+        msg: ServerMsg,
+    ) -> Option<ServerMsg> {
+        self.sent_messages.insert(peer, msg)
+    }
+
     /// Apply a change to the document.
+    ///
+    /// Changes that arrive before their dependencies are queued by automerge until the
+    /// dependencies are applied. Changes that can't be applied are recorded as invalid messages.
     pub fn apply_change(&mut self, change: Change) {
-        self.am.apply_changes(std::iter::once(change)).unwrap()
+        // applying a change we already have does nothing, so don't copy the history for it
+        if self.am.get_change_by_hash(&change.hash()).is_some() {
+            return;
+        }
+        let changes = std::iter::once(change);
+        let am = SharedHistory::make_mut(&mut self.am);
+        let res = match &mut self.patches {
+            Some(patches) => {
+                let mut observer = VecOpObserver::default();
+                let res = am.apply_changes_with(changes, Some(&mut observer));
+                patches.extend(observer.take_patches());
+                res
+            }
+            None => am.apply_changes(changes),
+        };
+        if let Err(_error) = res {
+            self.record_invalid_message();
+        }
+    }
+
+    /// Check whether this document has changes queued, waiting for their dependencies.
+    pub fn has_queued_changes(&self) -> bool {
+        !self.am.get_missing_deps(&[]).is_empty()
     }
 
     /// Receive a sync message for this document, automatically handling sync states.
+    ///
+    /// Messages that can't be applied are recorded as invalid messages. Messages still arriving
+    /// from peers that have left are applied without keeping a sync state for them.
     pub fn receive_sync_message(&mut self, peer: usize, message: sync::Message) {
-        let state = self.sync_states.entry(peer).or_default();
-        let res = self.am.receive_sync_message(state, message);
+        let mut departed_state = sync::State::new();
+        let state = if self.departed_peers.contains(&peer) {
+            &mut departed_state
+        } else {
+            self.sync_states.entry(peer).or_default()
+        };
+        let has_new_changes = message
+            .changes
+            .iter()
+            .any(|c| self.am.get_change_by_hash(&c.hash()).is_none());
+        if !has_new_changes && Arc::get_mut(&mut self.am).is_none() {
+            // only the sync state changes, so receive it with the spare copy rather than copying
+            // the shared history again
+            let res = {
+                let mut spare = self.am.spare.lock().unwrap();
+                let am = spare.get_or_insert_with(|| self.am.am.clone());
+                let res = am.receive_sync_message(state, message);
+                if res.is_err() {
+                    // the spare may no longer match the history
+                    *spare = None;
+                }
+                res
+            };
+            if let Err(_error) = res {
+                self.record_invalid_message();
+            }
+            self.assert_departed_forgotten();
+            return;
+        }
+        let am = SharedHistory::make_mut(&mut self.am);
+        let res = match &mut self.patches {
+            Some(patches) => {
+                let mut observer = VecOpObserver::default();
+                let res = am.receive_sync_message_with(state, message, &mut observer);
+                patches.extend(observer.take_patches());
+                res
+            }
+            None => am.receive_sync_message(state, message),
+        };
         if let Err(_error) = res {
-            // set the error
-            self.error = true;
+            self.record_invalid_message();
         }
+        self.assert_departed_forgotten();
     }
 
-    /// Generate a sync message for a peer.
+    /// Generate a sync message for a peer, `None` if there is nothing to send or the peer has
+    /// left.
     pub fn generate_sync_message(&mut self, peer: usize) -> Option<sync::Message> {
+        if self.departed_peers.contains(&peer) {
+            return None;
+        }
         let state = self.sync_states.entry(peer).or_default();
         let msg = self.am.generate_sync_message(state);
         if msg.is_some() {
@@ -243,24 +1215,237 @@ impl Document {
     }
 
     /// Save the document.
+    ///
+    /// The bytes are kept with the history, so clones sharing it don't save it again. Saving a
+    /// shared history saves a copy of it, which is thrown away afterwards.
     pub fn save(&mut self) -> Vec<u8> {
-        self.am.save()
+        if let Some(bytes) = self.am.saved.get() {
+            return bytes.clone();
+        }
+        let bytes = match Arc::get_mut(&mut self.am) {
+            Some(history) => history.am.save(),
+            None => self.am.am.clone().save(),
+        };
+        // another clone may have saved it meanwhile, which gives the same bytes
+        let _ = self.am.saved.set(bytes.clone());
+        bytes
     }
 
     /// Load a document, overwriting the current one.
     pub fn load(&mut self, bytes: &[u8]) {
-        let am = Automerge::load(bytes).unwrap();
-        self.am = am;
+        let mut am = Automerge::load(bytes).unwrap();
+        am.set_actor(self.am.get_actor().clone());
+        self.am = SharedHistory::new(am);
+    }
+
+    /// Save the changes since the last sync incrementally, updating the last sent heads.
+    ///
+    /// This produces the same chunks as `Automerge::save_incremental` but keeps track of what has
+    /// been saved in the last sent heads, which are part of the model state, rather than inside
+    /// automerge where the checker can't see it.
+    pub fn save_incremental(&mut self) -> Vec<u8> {
+        let bytes = self.save_incremental_after(&self.last_sent_heads);
+        self.update_last_sent_heads();
+        bytes
+    }
+
+    /// Save the changes after the given heads in the incremental format.
+    pub fn save_incremental_after(&self, heads: &[ChangeHash]) -> Vec<u8> {
+        self.am
+            .get_changes(heads)
+            .unwrap()
+            .into_iter()
+            .flat_map(|c| c.raw_bytes().iter().copied())
+            .collect()
+    }
+
+    /// Load incrementally saved chunks into the document.
+    ///
+    /// Chunks that can't be loaded are recorded as invalid messages.
+    pub fn load_incremental(&mut self, bytes: &[u8]) {
+        // loading changes we already have does nothing, so don't copy the history for them
+        let known = split_changes(bytes).map_or(false, |changes| {
+            changes
+                .iter()
+                .all(|c| self.am.get_change_by_hash(&c.hash()).is_some())
+        });
+        if known {
+            return;
+        }
+        let am = SharedHistory::make_mut(&mut self.am);
+        let res = match &mut self.patches {
+            Some(patches) => {
+                let mut observer = VecOpObserver::default();
+                let res = am.load_incremental_with(bytes, Some(&mut observer));
+                patches.extend(observer.take_patches());
+                res
+            }
+            None => am.load_incremental(bytes),
+        };
+        if let Err(_error) = res {
+            self.record_invalid_message();
+        }
+    }
+
+    /// Load a document from incrementally saved chunks, overwriting the current one.
+    pub fn load_from_incremental(&mut self, bytes: &[u8]) {
+        let mut am = Automerge::new();
+        am.set_actor(self.am.get_actor().clone());
+        am.load_incremental(bytes).unwrap();
+        self.am = SharedHistory::new(am);
     }
 
     /// Merge another document with this one.
+    ///
+    /// Documents that can't be merged are recorded as invalid messages.
     pub fn merge(&mut self, other: &mut Automerge) {
-        self.am.merge(other).unwrap();
+        // merging a document with nothing new does nothing, so don't copy the history for it
+        if self.am.get_changes_added(other).is_empty() {
+            return;
+        }
+        let am = SharedHistory::make_mut(&mut self.am);
+        let res = match &mut self.patches {
+            Some(patches) => {
+                let mut observer = VecOpObserver::default();
+                let res = am.merge_with(other, Some(&mut observer));
+                patches.extend(observer.take_patches());
+                res
+            }
+            None => am.merge(other),
+        };
+        if let Err(_error) = res {
+            self.record_invalid_message();
+        }
     }
 
     /// Create a transaction.
-    pub fn transaction(&mut self) -> Transaction<UnObserved> {
-        self.am.transaction()
+    ///
+    /// If this document is observing patches, the patches from the transaction are kept for
+    /// delivery to the application once it is committed.
+    pub fn transaction(&mut self) -> DocumentTransaction<'_> {
+        DocumentTransaction {
+            am: Some(&mut self.am),
+            patches: &mut self.patches,
+            txn: None,
+        }
+    }
+
+    /// Start observing patches from changes to this document.
+    pub fn observe_patches(&mut self) {
+        self.patches.get_or_insert_with(Vec::new);
+    }
+
+    /// Check whether this document is observing patches.
+    pub fn is_observing_patches(&self) -> bool {
+        self.patches.is_some()
+    }
+
+    /// Take the patches that haven't been delivered yet.
+    pub fn take_patches(&mut self) -> Vec<Patch> {
+        self.patches
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Get patches that build the whole document from empty, for when it has been replaced.
+    pub fn reload_patches(&self) -> Vec<Patch> {
+        let mut observer = VecOpObserver::default();
+        let changes = self.am.get_changes(&[]).unwrap().into_iter().cloned();
+        Automerge::new()
+            .apply_changes_with(changes, Some(&mut observer))
+            .unwrap();
+        observer.take_patches()
+    }
+
+    /// Forget the sync state for a peer.
+    pub(crate) fn remove_sync_state(&mut self, peer: usize) {
+        self.sync_states.remove(&peer);
+    }
+
+    /// Save a snapshot of the sync state for a peer, as it would be stored.
+    pub(crate) fn snapshot_sync_state(&mut self, peer: usize) {
+        match self.sync_states.get(&peer) {
+            Some(ss) => {
+                let snapshot = sync::State::decode(&ss.encode()).unwrap();
+                self.sync_state_snapshots.insert(peer, snapshot);
+            }
+            None => {
+                self.sync_state_snapshots.remove(&peer);
+            }
+        }
+    }
+
+    /// Roll the sync state for a peer back to the last snapshot of it, removing it if there is no
+    /// snapshot.
+    pub(crate) fn restore_sync_state(&mut self, peer: usize) {
+        match self.sync_state_snapshots.get(&peer) {
+            Some(snapshot) if !self.departed_peers.contains(&peer) => {
+                self.sync_states.insert(peer, snapshot.clone());
+            }
+            _ => {
+                self.sync_states.remove(&peer);
+            }
+        }
+        self.assert_departed_forgotten();
+    }
+
+    /// Create a copy of this document with the ids of servers remapped, for symmetry reduction.
+    ///
+    /// The history is rebuilt with the actor ids of servers remapped, which changes the hashes of
+    /// the changes, so the heads and the contents of sync states are remapped too. Peers are
+    /// remapped in the sync states and held messages.
+    ///
+    /// Returns `None` if the document can't be rewritten, such as when it has queued changes.
+    pub(crate) fn rewrite_ids(&self, rewrite: &mut IdRewrite) -> Option<Self> {
+        let am = rewrite.history(&self.am)?;
+        let partition = match &self.partition {
+            Some(partition) => Some(HeldMessages {
+                outbound: rewrite.held_messages(&partition.outbound)?,
+                inbound: rewrite.held_messages(&partition.inbound)?,
+                sent_heads: rewrite.heads(&partition.sent_heads),
+                dropped: partition.dropped,
+            }),
+            None => None,
+        };
+        let lossy_inbound = rewrite.held_messages(&self.lossy_inbound)?;
+        let sent_messages = self
+            .sent_messages
+            .iter()
+            .map(|(peer, msg)| Some((rewrite.id(*peer), rewrite.message(msg)?)))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            am,
+            sync_states: rewrite.sync_states(&self.sync_states),
+            last_sent_heads: rewrite.heads(&self.last_sent_heads),
+            error: self.error,
+            membership: self.membership,
+            departed_peers: self
+                .departed_peers
+                .iter()
+                .map(|peer| rewrite.id(*peer))
+                .collect(),
+            invalid_messages: self.invalid_messages,
+            debug_materialize: self.debug_materialize,
+            state_hashing: self.state_hashing,
+            initial_change_actor: self.initial_change_actor.clone(),
+            patches: self.patches.clone(),
+            persisted_heads: self.persisted_heads.as_deref().map(|h| rewrite.heads(h)),
+            persisted_sync_states: rewrite.sync_states(&self.persisted_sync_states),
+            sync_state_snapshots: rewrite.sync_states(&self.sync_state_snapshots),
+            partition,
+            lossy_inbound,
+            sent_messages,
+            sync_progress: self.sync_progress.as_ref().map(|progress| SyncProgress {
+                unproductive: progress
+                    .unproductive
+                    .iter()
+                    .map(|(peer, unproductive)| (rewrite.id(*peer), *unproductive))
+                    .collect(),
+                bound: progress.bound,
+            }),
+            causal_history: self.causal_history.clone(),
+        })
     }
 
     /// Reload sync states associated with this document.
@@ -273,6 +1458,342 @@ impl Document {
     }
 }
 
+/// Remapping of server ids across the documents and messages of a state, for symmetry reduction.
+///
+/// Rewriting the actor id of a change changes its hash, and so the hashes of the changes that
+/// depend on it. The new hashes are collected from the histories of all documents first so that
+/// heads, sync states and messages referring to changes from other documents can be remapped.
+/// Hashes of changes that no document has are left as they are.
+pub(crate) struct IdRewrite {
+    /// The new id for each old one, ids past the end stay the same.
+    ids: Vec<usize>,
+    /// New hashes of the changes in the histories added so far.
+    hashes: BTreeMap<ChangeHash, ChangeHash>,
+}
+
+/// A history with its actor ids rewritten.
+#[derive(Clone)]
+struct RewrittenHistory {
+    /// The rewritten history, `None` if it is the same as the original.
+    history: Option<Arc<SharedHistory>>,
+    /// New hashes of the changes in the history.
+    hashes: Arc<BTreeMap<ChangeHash, ChangeHash>>,
+}
+
+impl IdRewrite {
+    /// Create a rewrite giving each old id the new id at its index.
+    pub(crate) fn new(ids: Vec<usize>) -> Self {
+        Self {
+            ids,
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    /// Get the new id for an old one.
+    pub(crate) fn id(&self, id: usize) -> usize {
+        self.ids.get(id).copied().unwrap_or(id)
+    }
+
+    /// Rewrite an actor id in hex, only server ids are remapped, not the actor for the initial
+    /// change.
+    fn actor(&self, actor: &str) -> String {
+        match hex::decode(actor)
+            .ok()
+            .and_then(|bytes| <[u8; 8]>::try_from(bytes).ok())
+        {
+            Some(bytes) => {
+                let id = u64::from_be_bytes(bytes) as usize;
+                hex::encode((self.id(id) as u64).to_be_bytes())
+            }
+            None => actor.to_owned(),
+        }
+    }
+
+    /// Add the history of a document, so that references to its changes can be remapped, returning
+    /// `None` if it can't be rewritten.
+    pub(crate) fn add_document(&mut self, document: &Document) -> Option<()> {
+        self.history(&document.am).map(|_| ())
+    }
+
+    /// Get a history with its actor ids rewritten, adding its new hashes.
+    ///
+    /// The latest rewritten histories are kept with the original, so states sharing it don't
+    /// rebuild them.
+    fn history(&mut self, history: &Arc<SharedHistory>) -> Option<Arc<SharedHistory>> {
+        let cached = history
+            .rewrites
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(ids, _)| *ids == self.ids)
+            .map(|(_, rewritten)| rewritten.clone());
+        let rewritten = match cached {
+            Some(rewritten) => rewritten,
+            None => {
+                let rewritten = self.rewrite_history(history)?;
+                let mut rewrites = history.rewrites.lock().unwrap();
+                if rewrites.len() >= MAX_CACHED_REWRITES {
+                    rewrites.pop_front();
+                }
+                rewrites.push_back((self.ids.clone(), rewritten.clone()));
+                rewritten
+            }
+        };
+        self.hashes.extend(rewritten.hashes.iter());
+        Some(rewritten.history.unwrap_or_else(|| Arc::clone(history)))
+    }
+
+    fn rewrite_history(&self, history: &SharedHistory) -> Option<RewrittenHistory> {
+        // queued changes aren't part of the history that can be rebuilt
+        if !history.get_missing_deps(&[]).is_empty() {
+            return None;
+        }
+        let changes = history.get_changes(&[]).ok()?;
+        let unchanged = std::iter::once(history.get_actor())
+            .chain(changes.iter().map(|c| c.actor_id()))
+            .all(|actor| {
+                let actor = actor.to_hex_string();
+                self.actor(&actor) == actor
+            });
+        if unchanged {
+            // none of the actors are remapped so there's nothing to rebuild
+            let hashes = changes.iter().map(|c| (c.hash(), c.hash())).collect();
+            return Some(RewrittenHistory {
+                history: None,
+                hashes: Arc::new(hashes),
+            });
+        }
+        let (am, hashes) = rewrite_history(history, &|actor| self.actor(actor))?;
+        Some(RewrittenHistory {
+            history: Some(SharedHistory::new(am)),
+            hashes: Arc::new(hashes),
+        })
+    }
+
+    /// Rewrite a change, returning `None` if it can't be rebuilt.
+    fn change(&self, change: &Change) -> Option<Change> {
+        if self.hashes.get(&change.hash()) == Some(&change.hash()) {
+            return Some(change.clone());
+        }
+        rewrite_change(change, &|actor| self.actor(actor), &self.hashes)
+    }
+
+    fn hash(&self, hash: &ChangeHash) -> ChangeHash {
+        self.hashes.get(hash).copied().unwrap_or(*hash)
+    }
+
+    /// Rewrite a set of hashes, sorting them as their order changes.
+    pub(crate) fn heads(&self, heads: &[ChangeHash]) -> Vec<ChangeHash> {
+        let mut heads = heads.iter().map(|h| self.hash(h)).collect::<Vec<_>>();
+        heads.sort();
+        heads
+    }
+
+    /// Rewrite a bloom filter, rebuilding it from the known changes that it contains.
+    fn bloom(&self, bloom: &sync::BloomFilter) -> sync::BloomFilter {
+        let contained = self
+            .hashes
+            .iter()
+            .filter(|(old, _)| bloom.contains_hash(old))
+            .map(|(_, new)| *new)
+            .collect::<Vec<_>>();
+        sync::BloomFilter::from_hashes(contained.iter())
+    }
+
+    fn have(&self, have: &sync::Have) -> sync::Have {
+        let mut new_have = have.clone();
+        new_have.last_sync = self.heads(&have.last_sync);
+        new_have.bloom = self.bloom(&have.bloom);
+        new_have
+    }
+
+    fn sync_state(&self, state: &sync::State) -> sync::State {
+        let mut new_state = state.clone();
+        new_state.shared_heads = self.heads(&state.shared_heads);
+        new_state.last_sent_heads = self.heads(&state.last_sent_heads);
+        new_state.their_heads = state.their_heads.as_deref().map(|h| self.heads(h));
+        new_state.their_need = state.their_need.as_deref().map(|h| self.heads(h));
+        new_state.their_have = state
+            .their_have
+            .as_ref()
+            .map(|haves| haves.iter().map(|have| self.have(have)).collect());
+        new_state.sent_hashes = state.sent_hashes.iter().map(|h| self.hash(h)).collect();
+        new_state
+    }
+
+    /// Rewrite sync states along with the peers they are for.
+    pub(crate) fn sync_states(
+        &self,
+        states: &BTreeMap<usize, sync::State>,
+    ) -> BTreeMap<usize, sync::State> {
+        states
+            .iter()
+            .map(|(peer, state)| (self.id(*peer), self.sync_state(state)))
+            .collect()
+    }
+
+    fn held_messages(&self, held: &[(usize, ServerMsg)]) -> Option<Vec<(usize, ServerMsg)>> {
+        held.iter()
+            .map(|(peer, msg)| Some((self.id(*peer), self.message(msg)?)))
+            .collect()
+    }
+
+    /// Rewrite the payload of a message, returning `None` if it can't be decoded and rebuilt.
+    pub(crate) fn message(&self, msg: &ServerMsg) -> Option<ServerMsg> {
+        let rewrite_changes = |changes: Vec<Change>| -> Option<Vec<u8>> {
+            let mut bytes = Vec::new();
+            for change in changes {
+                bytes.extend(self.change(&change)?.raw_bytes());
+            }
+            Some(bytes)
+        };
+        let msg = match msg {
+            ServerMsg::SyncChangeRaw {
+                missing_changes_bytes,
+            } => ServerMsg::SyncChangeRaw {
+                missing_changes_bytes: missing_changes_bytes
+                    .iter()
+                    .map(|bytes| {
+                        let change = Change::from_bytes(bytes.0.clone()).ok()?;
+                        rewrite_changes(vec![change]).map(Bytes)
+                    })
+                    .collect::<Option<_>>()?,
+            },
+            ServerMsg::SyncMessageRaw { message_bytes } => {
+                let mut message = sync::Message::decode(&message_bytes.0).ok()?;
+                message.heads = self.heads(&message.heads);
+                message.need = self.heads(&message.need);
+                message.have = message.have.iter().map(|have| self.have(have)).collect();
+                message.changes = message
+                    .changes
+                    .iter()
+                    .map(|change| self.change(change))
+                    .collect::<Option<_>>()?;
+                ServerMsg::SyncMessageRaw {
+                    message_bytes: Bytes(message.encode()),
+                }
+            }
+            ServerMsg::SyncSaveLoadRaw { doc_bytes } => {
+                let am = Automerge::load(&doc_bytes.0).ok()?;
+                let (mut am, _) = rewrite_history(&am, &|actor| self.actor(actor))?;
+                ServerMsg::SyncSaveLoadRaw {
+                    doc_bytes: Bytes(am.save()),
+                }
+            }
+            ServerMsg::SyncIncrementalSaveLoadRaw { chunk_bytes } => {
+                ServerMsg::SyncIncrementalSaveLoadRaw {
+                    chunk_bytes: Bytes(rewrite_changes(split_changes(&chunk_bytes.0)?)?),
+                }
+            }
+            ServerMsg::Join | ServerMsg::Leave => msg.clone(),
+        };
+        Some(msg)
+    }
+}
+
+/// Split concatenated change chunks, as saved by [`Document::save_incremental_after`], into
+/// changes.
+fn split_changes(mut bytes: &[u8]) -> Option<Vec<Change>> {
+    let mut changes = Vec::new();
+    while !bytes.is_empty() {
+        // the magic bytes, checksum and chunk type come before the length of the chunk
+        let mut end = 9;
+        let mut len = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = *bytes.get(end)?;
+            end += 1;
+            len |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+            if shift > 56 {
+                return None;
+            }
+        }
+        let end = end.checked_add(len)?;
+        changes.push(Change::from_bytes(bytes.get(..end)?.to_vec()).ok()?);
+        bytes = &bytes[end..];
+    }
+    Some(changes)
+}
+
+/// Rebuild a history with actor ids rewritten, returning the new automerge object and the mapping
+/// from old to new change hashes, or `None` if it can't be rebuilt.
+fn rewrite_history(
+    am: &Automerge,
+    rewrite_actor: &dyn Fn(&str) -> String,
+) -> Option<(Automerge, BTreeMap<ChangeHash, ChangeHash>)> {
+    let mut hashes = BTreeMap::new();
+    let mut new_am = Automerge::new();
+    for change in am.get_changes(&[]).ok()? {
+        let new_change = rewrite_change(change, rewrite_actor, &hashes)?;
+        hashes.insert(change.hash(), new_change.hash());
+        new_am.apply_changes(std::iter::once(new_change)).ok()?;
+    }
+    let actor = hex::decode(rewrite_actor(&am.get_actor().to_hex_string())).ok()?;
+    new_am.set_actor(ActorId::from(actor));
+    Some((new_am, hashes))
+}
+
+/// Rebuild a change with actor ids rewritten and its dependencies replaced by their new hashes,
+/// returning `None` if it can't be rebuilt.
+fn rewrite_change(
+    change: &Change,
+    rewrite_actor: &dyn Fn(&str) -> String,
+    hashes: &BTreeMap<ChangeHash, ChangeHash>,
+) -> Option<Change> {
+    let rewrite_hash = |hash: &str| -> String {
+        match hash.parse::<ChangeHash>().ok().and_then(|h| hashes.get(&h)) {
+            Some(new_hash) => hex::encode(new_hash.0),
+            None => hash.to_owned(),
+        }
+    };
+    let mut json = serde_json::to_value(change.decode()).ok()?;
+    rewrite_change_json(&mut json, None, rewrite_actor, &rewrite_hash);
+    let expanded: automerge::ExpandedChange = serde_json::from_value(json).ok()?;
+    Some(Change::from(expanded))
+}
+
+/// Rewrite the actor ids and dependency hashes in the json form of an expanded change.
+fn rewrite_change_json(
+    json: &mut serde_json::Value,
+    key: Option<&str>,
+    rewrite_actor: &dyn Fn(&str) -> String,
+    rewrite_hash: &dyn Fn(&str) -> String,
+) {
+    match json {
+        serde_json::Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                match k.as_str() {
+                    // values are application data so should be left alone
+                    "value" => {}
+                    // the hash is recomputed when building the new change
+                    "hash" => *v = serde_json::Value::Null,
+                    k => rewrite_change_json(v, Some(k), rewrite_actor, rewrite_hash),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for v in values {
+                rewrite_change_json(v, key, rewrite_actor, rewrite_hash);
+            }
+        }
+        serde_json::Value::String(s) => match key {
+            Some("actor") => *s = rewrite_actor(s),
+            Some("deps") => *s = rewrite_hash(s),
+            Some("obj" | "elemId" | "pred") => {
+                if let Some((counter, actor)) = s.split_once('@') {
+                    *s = format!("{}@{}", counter, rewrite_actor(actor));
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
 impl Deref for Document {
     type Target = Automerge;
 
@@ -280,3 +1801,324 @@ impl Deref for Document {
         &self.am
     }
 }
+
+/// A transaction on a document.
+///
+/// The automerge object isn't copied from clones sharing it until the first change is made, so
+/// transactions that only read, or that are rolled back before changing anything, leave it shared.
+///
+/// When the document is observing patches the changes are observed, and their patches are kept for
+/// delivery to the application once committed.
+pub struct DocumentTransaction<'a> {
+    /// The shared automerge object, until the first change starts the transaction on it.
+    am: Option<&'a mut Arc<SharedHistory>>,
+    /// Patches of the document, if it is observing them.
+    patches: &'a mut Option<Vec<Patch>>,
+    txn: Option<InnerTransaction<'a>>,
+}
+
+/// An automerge transaction, observed if the document is observing patches.
+enum InnerTransaction<'a> {
+    UnObserved(Transaction<'a, UnObserved>),
+    Observed(Transaction<'a, Observed<VecOpObserver>>),
+}
+
+/// Run an expression with the transaction, whether it is observed or not.
+macro_rules! with_txn {
+    ($txn:expr, $t:ident => $e:expr) => {
+        match $txn {
+            InnerTransaction::UnObserved($t) => $e,
+            InnerTransaction::Observed($t) => $e,
+        }
+    };
+}
+
+/// Run a read with the transaction once it has started, or the shared automerge object before.
+macro_rules! read {
+    ($self:expr, $d:ident => $e:expr) => {
+        match &$self.txn {
+            Some(txn) => with_txn!(txn, $d => $e),
+            None => {
+                let $d = $self.shared();
+                $e
+            }
+        }
+    };
+}
+
+impl<'a> DocumentTransaction<'a> {
+    /// Get the automerge object to read from before any changes are made.
+    fn shared(&self) -> &Automerge {
+        self.am.as_deref().expect("transaction without a document")
+    }
+
+    /// Get the transaction to make changes in, starting it if this is the first change.
+    fn txn(&mut self) -> &mut InnerTransaction<'a> {
+        if let Some(am) = self.am.take() {
+            let am = SharedHistory::make_mut(am);
+            self.txn = Some(if self.patches.is_some() {
+                InnerTransaction::Observed(am.transaction_with_observer(VecOpObserver::default()))
+            } else {
+                InnerTransaction::UnObserved(am.transaction())
+            });
+        }
+        self.txn.as_mut().expect("transaction without a document")
+    }
+
+    /// Commit the changes made, if any, keeping their patches if the document is observing them.
+    pub fn commit(self) -> Option<ChangeHash> {
+        match self.txn? {
+            InnerTransaction::UnObserved(txn) => txn.commit(),
+            InnerTransaction::Observed(txn) => {
+                let (mut observer, hash) = txn.commit();
+                if let Some(patches) = self.patches {
+                    patches.extend(observer.take_patches());
+                }
+                hash
+            }
+        }
+    }
+
+    /// Throw away the changes made, returning the number of operations thrown away.
+    pub fn rollback(self) -> usize {
+        self.txn.map_or(0, |txn| with_txn!(txn, t => t.rollback()))
+    }
+}
+
+impl<'a> ReadDoc for DocumentTransaction<'a> {
+    fn keys<O: AsRef<ObjId>>(&self, obj: O) -> Keys<'_, '_> {
+        read!(self, d => d.keys(obj))
+    }
+
+    fn keys_at<O: AsRef<ObjId>>(&self, obj: O, heads: &[ChangeHash]) -> KeysAt<'_, '_> {
+        read!(self, d => d.keys_at(obj, heads))
+    }
+
+    fn map_range<O: AsRef<ObjId>, R: RangeBounds<String>>(
+        &self,
+        obj: O,
+        range: R,
+    ) -> MapRange<'_, R> {
+        read!(self, d => d.map_range(obj, range))
+    }
+
+    fn map_range_at<O: AsRef<ObjId>, R: RangeBounds<String>>(
+        &self,
+        obj: O,
+        range: R,
+        heads: &[ChangeHash],
+    ) -> MapRangeAt<'_, R> {
+        read!(self, d => d.map_range_at(obj, range, heads))
+    }
+
+    fn list_range<O: AsRef<ObjId>, R: RangeBounds<usize>>(
+        &self,
+        obj: O,
+        range: R,
+    ) -> ListRange<'_, R> {
+        read!(self, d => d.list_range(obj, range))
+    }
+
+    fn list_range_at<O: AsRef<ObjId>, R: RangeBounds<usize>>(
+        &self,
+        obj: O,
+        range: R,
+        heads: &[ChangeHash],
+    ) -> ListRangeAt<'_, R> {
+        read!(self, d => d.list_range_at(obj, range, heads))
+    }
+
+    fn values<O: AsRef<ObjId>>(&self, obj: O) -> Values<'_> {
+        read!(self, d => d.values(obj))
+    }
+
+    fn values_at<O: AsRef<ObjId>>(&self, obj: O, heads: &[ChangeHash]) -> Values<'_> {
+        read!(self, d => d.values_at(obj, heads))
+    }
+
+    fn length<O: AsRef<ObjId>>(&self, obj: O) -> usize {
+        read!(self, d => d.length(obj))
+    }
+
+    fn length_at<O: AsRef<ObjId>>(&self, obj: O, heads: &[ChangeHash]) -> usize {
+        read!(self, d => d.length_at(obj, heads))
+    }
+
+    fn object_type<O: AsRef<ObjId>>(&self, obj: O) -> Result<ObjType, AutomergeError> {
+        read!(self, d => d.object_type(obj))
+    }
+
+    fn text<O: AsRef<ObjId>>(&self, obj: O) -> Result<String, AutomergeError> {
+        read!(self, d => d.text(obj))
+    }
+
+    fn text_at<O: AsRef<ObjId>>(
+        &self,
+        obj: O,
+        heads: &[ChangeHash],
+    ) -> Result<String, AutomergeError> {
+        read!(self, d => d.text_at(obj, heads))
+    }
+
+    fn get<O: AsRef<ObjId>, P: Into<Prop>>(
+        &self,
+        obj: O,
+        prop: P,
+    ) -> Result<Option<(automerge::Value<'_>, ObjId)>, AutomergeError> {
+        read!(self, d => d.get(obj, prop))
+    }
+
+    fn get_at<O: AsRef<ObjId>, P: Into<Prop>>(
+        &self,
+        obj: O,
+        prop: P,
+        heads: &[ChangeHash],
+    ) -> Result<Option<(automerge::Value<'_>, ObjId)>, AutomergeError> {
+        read!(self, d => d.get_at(obj, prop, heads))
+    }
+
+    fn get_all<O: AsRef<ObjId>, P: Into<Prop>>(
+        &self,
+        obj: O,
+        prop: P,
+    ) -> Result<Vec<(automerge::Value<'_>, ObjId)>, AutomergeError> {
+        read!(self, d => d.get_all(obj, prop))
+    }
+
+    fn get_all_at<O: AsRef<ObjId>, P: Into<Prop>>(
+        &self,
+        obj: O,
+        prop: P,
+        heads: &[ChangeHash],
+    ) -> Result<Vec<(automerge::Value<'_>, ObjId)>, AutomergeError> {
+        read!(self, d => d.get_all_at(obj, prop, heads))
+    }
+
+    fn parents<O: AsRef<ObjId>>(&self, obj: O) -> Result<Parents<'_>, AutomergeError> {
+        read!(self, d => d.parents(obj))
+    }
+
+    fn get_missing_deps(&self, heads: &[ChangeHash]) -> Vec<ChangeHash> {
+        read!(self, d => d.get_missing_deps(heads))
+    }
+
+    fn get_change_by_hash(&self, hash: &ChangeHash) -> Option<&Change> {
+        read!(self, d => d.get_change_by_hash(hash))
+    }
+}
+
+/// Changes start the transaction on the automerge object, copying it first if it is shared.
+impl<'a> Transactable for DocumentTransaction<'a> {
+    fn pending_ops(&self) -> usize {
+        self.txn
+            .as_ref()
+            .map_or(0, |txn| with_txn!(txn, t => t.pending_ops()))
+    }
+
+    fn put<O: AsRef<ObjId>, P: Into<Prop>, V: Into<ScalarValue>>(
+        &mut self,
+        obj: O,
+        prop: P,
+        value: V,
+    ) -> Result<(), AutomergeError> {
+        with_txn!(self.txn(), t => t.put(obj, prop, value))
+    }
+
+    fn put_object<O: AsRef<ObjId>, P: Into<Prop>>(
+        &mut self,
+        obj: O,
+        prop: P,
+        object: ObjType,
+    ) -> Result<ObjId, AutomergeError> {
+        with_txn!(self.txn(), t => t.put_object(obj, prop, object))
+    }
+
+    fn insert<O: AsRef<ObjId>, V: Into<ScalarValue>>(
+        &mut self,
+        obj: O,
+        index: usize,
+        value: V,
+    ) -> Result<(), AutomergeError> {
+        with_txn!(self.txn(), t => t.insert(obj, index, value))
+    }
+
+    fn insert_object<O: AsRef<ObjId>>(
+        &mut self,
+        obj: O,
+        index: usize,
+        object: ObjType,
+    ) -> Result<ObjId, AutomergeError> {
+        with_txn!(self.txn(), t => t.insert_object(obj, index, object))
+    }
+
+    fn increment<O: AsRef<ObjId>, P: Into<Prop>>(
+        &mut self,
+        obj: O,
+        prop: P,
+        value: i64,
+    ) -> Result<(), AutomergeError> {
+        with_txn!(self.txn(), t => t.increment(obj, prop, value))
+    }
+
+    fn delete<O: AsRef<ObjId>, P: Into<Prop>>(
+        &mut self,
+        obj: O,
+        prop: P,
+    ) -> Result<(), AutomergeError> {
+        with_txn!(self.txn(), t => t.delete(obj, prop))
+    }
+
+    fn splice<O: AsRef<ObjId>, V: IntoIterator<Item = ScalarValue>>(
+        &mut self,
+        obj: O,
+        pos: usize,
+        del: usize,
+        vals: V,
+    ) -> Result<(), AutomergeError> {
+        with_txn!(self.txn(), t => t.splice(obj, pos, del, vals))
+    }
+
+    fn splice_text<O: AsRef<ObjId>>(
+        &mut self,
+        obj: O,
+        pos: usize,
+        del: usize,
+        text: &str,
+    ) -> Result<(), AutomergeError> {
+        with_txn!(self.txn(), t => t.splice_text(obj, pos, del, text))
+    }
+
+    fn base_heads(&self) -> Vec<ChangeHash> {
+        match &self.txn {
+            Some(txn) => with_txn!(txn, t => t.base_heads()),
+            // the transaction would start from the current heads
+            None => self.shared().get_heads(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_rolls_back_to_persisted_heads() {
+        let mut doc = Document::new(0);
+        doc.with_initial_change(|txn| {
+            txn.put(ROOT, "counter", ScalarValue::counter(0)).unwrap();
+        });
+        doc.persist();
+        let persisted = doc.get_heads();
+
+        let mut txn = doc.transaction();
+        txn.increment(ROOT, "counter", 1).unwrap();
+        txn.commit();
+        assert_ne!(doc.get_heads(), persisted);
+
+        doc.rollback_to_persisted();
+        assert_eq!(doc.get_heads(), persisted);
+        assert_eq!(doc.persisted_heads, Some(persisted));
+        // the document keeps its actor to make new changes with
+        assert_eq!(doc.get_actor(), &ActorId::from(0usize.to_be_bytes()));
+    }
+}
@@ -0,0 +1,195 @@
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use automerge::ChangeHash;
+
+/// An application input, recorded without the type of the application.
+///
+/// Inputs are hashed once when recorded, and compared by value.
+#[derive(Clone)]
+pub struct RecordedInput {
+    hash: u64,
+    value: Arc<dyn Any + Send + Sync>,
+    debug: fn(&(dyn Any + Send + Sync), &mut std::fmt::Formatter<'_>) -> std::fmt::Result,
+    eq: fn(&(dyn Any + Send + Sync), &(dyn Any + Send + Sync)) -> bool,
+}
+
+impl RecordedInput {
+    /// Record an input.
+    pub fn new<I>(input: &I) -> Self
+    where
+        I: Clone + Hash + Eq + Debug + Send + Sync + 'static,
+    {
+        let mut hasher = DefaultHasher::new();
+        input.hash(&mut hasher);
+        Self {
+            hash: hasher.finish(),
+            value: Arc::new(input.clone()),
+            debug: |value, f| value.downcast_ref::<I>().unwrap().fmt(f),
+            // inputs of different types are never equal
+            eq: |value, other| value.downcast_ref::<I>() == other.downcast_ref::<I>(),
+        }
+    }
+
+    /// Get the input, if it is of the given type.
+    pub fn downcast_ref<I: 'static>(&self) -> Option<&I> {
+        self.value.downcast_ref()
+    }
+}
+
+impl Debug for RecordedInput {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (self.debug)(&*self.value, f)
+    }
+}
+
+impl PartialEq for RecordedInput {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && (self.eq)(&*self.value, &*other.value)
+    }
+}
+
+impl Eq for RecordedInput {}
+
+impl Hash for RecordedInput {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state);
+    }
+}
+
+/// An application input processed by a server.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Operation {
+    /// The input that was processed.
+    pub input: RecordedInput,
+    /// Changes made by the input, in the order they were made.
+    pub produced: Vec<ChangeHash>,
+    /// Changes in the document once the input was processed, when its output was returned.
+    pub visible: BTreeSet<ChangeHash>,
+}
+
+/// History of the inputs processed by a server, in the order they were processed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CausalHistory {
+    operations: Vec<Operation>,
+}
+
+impl CausalHistory {
+    /// Record an input being processed.
+    pub fn record(&mut self, operation: Operation) {
+        self.operations.push(operation);
+    }
+
+    /// Get the recorded operations.
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+}
+
+/// Check that the histories of all servers are causally consistent.
+///
+/// Each operation must see everything that was visible to the earlier operations of the same
+/// server, and for each change it sees, everything that was visible when that change was made.
+pub fn causally_consistent<'a>(histories: impl IntoIterator<Item = &'a CausalHistory>) -> bool {
+    let histories = histories.into_iter().collect::<Vec<_>>();
+
+    // what was visible before each change was made
+    let mut predecessors = BTreeMap::new();
+    for history in &histories {
+        for operation in &history.operations {
+            for (i, hash) in operation.produced.iter().enumerate() {
+                let mut before = operation.visible.clone();
+                for later in &operation.produced[i..] {
+                    before.remove(later);
+                }
+                predecessors.insert(*hash, before);
+            }
+        }
+    }
+
+    histories.iter().all(|history| {
+        let session_order = history
+            .operations
+            .windows(2)
+            .all(|w| w[0].visible.is_subset(&w[1].visible));
+        let causal_order = history.operations.iter().all(|operation| {
+            operation.visible.iter().all(|hash| {
+                predecessors
+                    .get(hash)
+                    .map_or(true, |before| before.is_subset(&operation.visible))
+            })
+        });
+        session_order && causal_order
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(n: u8) -> ChangeHash {
+        ChangeHash([n; 32])
+    }
+
+    fn operation(produced: &[u8], visible: &[u8]) -> Operation {
+        Operation {
+            input: RecordedInput::new(&produced.to_vec()),
+            produced: produced.iter().copied().map(hash).collect(),
+            visible: visible.iter().copied().map(hash).collect(),
+        }
+    }
+
+    fn history(operations: Vec<Operation>) -> CausalHistory {
+        let mut history = CausalHistory::default();
+        for operation in operations {
+            history.record(operation);
+        }
+        history
+    }
+
+    #[test]
+    fn recorded_inputs_compare_by_value() {
+        assert_eq!(RecordedInput::new(&1u32), RecordedInput::new(&1u32));
+        assert_ne!(RecordedInput::new(&1u32), RecordedInput::new(&2u32));
+        assert_ne!(RecordedInput::new(&1u32), RecordedInput::new(&1u64));
+    }
+
+    #[test]
+    fn no_histories_are_consistent() {
+        assert!(causally_consistent(std::iter::empty()));
+        assert!(causally_consistent(&[CausalHistory::default()]));
+    }
+
+    #[test]
+    fn seeing_what_changes_saw_is_consistent() {
+        let a = history(vec![operation(&[1], &[1]), operation(&[], &[1, 2])]);
+        let b = history(vec![operation(&[2], &[1, 2])]);
+        assert!(causally_consistent(&[a, b]));
+    }
+
+    #[test]
+    fn forgetting_changes_is_inconsistent() {
+        let a = history(vec![operation(&[1], &[1]), operation(&[], &[])]);
+        assert!(!causally_consistent(&[a]));
+    }
+
+    #[test]
+    fn seeing_a_change_without_its_predecessors_is_inconsistent() {
+        let a = history(vec![operation(&[1], &[1]), operation(&[2], &[1, 2])]);
+        let b = history(vec![operation(&[], &[2])]);
+        assert!(!causally_consistent(&[a, b]));
+    }
+
+    #[test]
+    fn changes_from_one_input_depend_on_the_earlier_ones() {
+        let a = history(vec![operation(&[1, 2], &[1, 2])]);
+        let b = history(vec![operation(&[], &[1])]);
+        let c = history(vec![operation(&[], &[2])]);
+        assert!(causally_consistent(&[a.clone(), b]));
+        assert!(!causally_consistent(&[a, c]));
+    }
+}
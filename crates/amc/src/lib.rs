@@ -30,15 +30,33 @@ pub mod global;
 /// Utilities for built-in properties.
 pub mod properties;
 
+/// Symmetry reduction for models with interchangeable servers.
+pub mod symmetry;
+
+/// Histories of what applications observed, for checking consistency.
+pub mod history;
+
+/// Checking pairs of application inputs commute, without model checking.
+pub mod commute;
+
 /// User application implementations.
 pub mod application {
     pub use crate::client::Application;
     pub use crate::client::DerefDocument;
+    pub use crate::client::Specification;
     pub use crate::document::Document;
+    pub use crate::document::DocumentTransaction;
+    pub use crate::document::{
+        materialize, materialize_at, materialize_conflicts, ActorIds, ConflictValue, Conflicts,
+        DocumentDiff, Scalar, StateHashing, Value, ValueDiff,
+    };
 
     /// Wrappers around applications to handle syncing.
     pub mod server {
-        pub use crate::server::{Server, ServerMsg, SyncMethod};
+        pub use crate::server::{
+            Fault, JoinMode, PartitionMode, RestartMode, Server, ServerMsg, SyncMethod,
+            SyncStateResetMode,
+        };
     }
 }
 
@@ -1,20 +1,24 @@
 use crate::{
     application::{
-        server::{Server, SyncMethod},
-        Application,
+        server::{
+            Fault, JoinMode, PartitionMode, RestartMode, Server, SyncMethod, SyncStateResetMode,
+        },
+        ActorIds, Application, StateHashing,
     },
     client::Client,
     driver::Drive,
     global::{GlobalActor, GlobalMsg},
-    properties,
+    properties::{self, Check},
 };
 use clap::Args;
 use stateright::{
     actor::{model_peers, ActorModel, Envelope, Id, Network},
     Property,
 };
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::str::FromStr;
 
 /// Builder of a model.
 pub trait ModelBuilder: Debug {
@@ -47,6 +51,14 @@ pub trait ModelBuilder: Debug {
         &self,
     ) -> Vec<Property<ActorModel<GlobalActor<Self::App, Self::Driver>, Self::Config, Self::History>>>;
 
+    /// Generate extra checks to register alongside the built-in ones, selectable by name.
+    fn checks(
+        &self,
+    ) -> Vec<Check<ActorModel<GlobalActor<Self::App, Self::Driver>, Self::Config, Self::History>>>
+    {
+        Vec::new()
+    }
+
     /// Record an input to the application.
     fn record_input(
         &self,
@@ -71,12 +83,18 @@ pub trait ModelBuilder: Debug {
 }
 
 /// Options for running a model.
-#[derive(Args, Debug)]
+#[derive(Args, Clone, Debug, PartialEq)]
 pub struct ModelOpts {
     /// Number of servers to run.
     #[clap(long, short, global = true, default_value = "2")]
     pub servers: usize,
 
+    /// Number of relays to run alongside the servers.
+    ///
+    /// Relays take part in syncing, forwarding what they receive, but have no drivers.
+    #[clap(long, global = true, default_value = "0")]
+    pub relays: usize,
+
     /// Method to sync changes between servers.
     #[clap(long, global = true, default_value = "changes")]
     pub sync_method: SyncMethod,
@@ -85,10 +103,113 @@ pub struct ModelOpts {
     #[clap(long, global = true)]
     pub batch_synchronisation: bool,
 
+    /// Whether to send each change in its own message so that they can arrive before their
+    /// dependencies, for use with the changes sync method and an unordered network.
+    #[clap(long, global = true)]
+    pub out_of_order_changes: bool,
+
+    /// Make the last server a faulty peer, corrupting the payloads it sends in the given way.
+    #[clap(long, global = true)]
+    pub faulty_peer: Option<Fault>,
+
     /// Whether to perform server restarts.
     #[clap(long, global = true)]
     pub restarts: bool,
 
+    /// How servers restart.
+    #[clap(long, global = true, default_value = "save-load")]
+    pub restart_mode: RestartMode,
+
+    /// Number of servers, taken from the last, that join the network late.
+    #[clap(long, global = true, default_value = "0")]
+    pub late_joiners: usize,
+
+    /// What servers that join late start from.
+    #[clap(long, global = true, default_value = "empty")]
+    pub join_mode: JoinMode,
+
+    /// Number of servers, taken from the first, that leave the network permanently.
+    #[clap(long, global = true, default_value = "0")]
+    pub leavers: usize,
+
+    /// Whether servers forget their sync state for a peer at times, or roll it back to a stale
+    /// snapshot, while the peer keeps its own.
+    ///
+    /// Only the `messages` sync method keeps sync states, so this needs it.
+    #[clap(long, global = true)]
+    pub sync_state_resets: bool,
+
+    /// How servers forget their sync state for a peer.
+    #[clap(long, global = true, default_value = "drop")]
+    pub sync_state_reset_mode: SyncStateResetMode,
+
+    /// Whether to partition servers from their peers, and later heal them.
+    #[clap(long, global = true)]
+    pub partitions: bool,
+
+    /// Servers on one side of the partition, cut off from those on the other side while the
+    /// others stay connected. Each of these servers partitions and heals on its own, so partial
+    /// splits are explored on the way to the full cut.
+    ///
+    /// By default each server is partitioned from all of its peers.
+    #[clap(long, global = true, value_delimiter = ',')]
+    pub partition_cut: Vec<usize>,
+
+    /// What happens to messages that would cross a partition.
+    #[clap(long, global = true, default_value = "hold")]
+    pub partition_mode: PartitionMode,
+
+    /// Topology of the connections between servers.
+    ///
+    /// One of `mesh`, `star`, `ring`, `line` or a custom adjacency list such as `0:1;1:0,2;2:1`,
+    /// which must list each connection from both ends.
+    #[clap(long, global = true, default_value = "mesh")]
+    pub topology: Topology,
+
+    /// Semantics of the network between actors.
+    #[clap(long, global = true, default_value = "ordered")]
+    pub network: NetworkType,
+
+    /// How servers pick the actor ids for their documents.
+    #[clap(long, global = true, default_value = "index")]
+    pub actor_ids: ActorIds,
+
+    /// Actor, as a number, that makes the initial change of documents.
+    #[clap(long, global = true, default_value = "999")]
+    pub initial_change_actor: u64,
+
+    /// Whether server documents observe patches, delivering them to the application.
+    #[clap(long, global = true)]
+    pub observe_patches: bool,
+
+    /// Number of sync messages in a row from a peer that don't change a server's document before
+    /// syncing is considered livelocked, checked by the `convergence` check which requires it.
+    #[clap(long, global = true)]
+    pub unproductive_sync_bound: Option<usize>,
+
+    /// Record the changes each input made and could see, checked by the `causal` check.
+    #[clap(long, global = true)]
+    pub causal_history: bool,
+
+    /// How server states are hashed when deduplicating them.
+    ///
+    /// `semantic` trades precision for a smaller state space by treating documents with the same
+    /// content and syncing left to do as the same.
+    #[clap(long, global = true, default_value = "exact")]
+    pub state_hashing: StateHashing,
+
+    /// Treat servers as interchangeable, only exploring one of each set of symmetric states.
+    ///
+    /// Servers must be built from the same application with matching drivers.
+    #[clap(long, global = true)]
+    pub symmetry: bool,
+
+    /// Names of checks to enable, such as `in-sync,save-load`.
+    ///
+    /// These are combined with the checks enabled by their individual flags.
+    #[clap(long = "check", global = true, value_delimiter = ',')]
+    pub checks: Vec<String>,
+
     /// Enable checking documents are in sync and don't have any other messages.
     #[clap(long, global = true)]
     pub in_sync_check: bool,
@@ -107,7 +228,194 @@ pub struct ModelOpts {
     pub error_free_check: bool,
 }
 
+/// Types of network to connect actors with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum NetworkType {
+    /// Messages between a pair of actors are delivered in order, exactly once.
+    Ordered,
+    /// Messages can be delivered in any order, exactly once.
+    Unordered,
+    /// Messages between a pair of actors are delivered in order but any message between servers
+    /// may be dropped.
+    ///
+    /// Servers hold messages from their peers until they either process or lose them, so inputs
+    /// from clients and their outputs are never lost.
+    Lossy,
+    /// Messages can be delivered in any order and any number of times.
+    Duplicating,
+}
+
+/// Topology of the connections between servers.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Topology {
+    /// Every server is connected to every other server.
+    Mesh,
+    /// Every server is connected to a hub, the first relay if there are any or the first server
+    /// otherwise.
+    Star,
+    /// Servers are connected to their neighbours, with the last connected back to the first.
+    Ring,
+    /// Servers are connected to their neighbours.
+    Line,
+    /// Servers are connected to the peers listed for them.
+    ///
+    /// Connections go both ways, so each server must be listed as a peer of its peers.
+    Custom(BTreeMap<usize, Vec<usize>>),
+}
+
+impl Topology {
+    /// Get the peers of a server in this topology.
+    ///
+    /// Relays are numbered after the servers.
+    pub fn peers(&self, server: usize, servers: usize, relays: usize) -> Vec<Id> {
+        let hub = if relays > 0 { servers } else { 0 };
+        let servers = servers + relays;
+        match self {
+            Topology::Mesh => model_peers(server, servers),
+            Topology::Star => {
+                if server == hub {
+                    model_peers(server, servers)
+                } else {
+                    vec![Id::from(hub)]
+                }
+            }
+            Topology::Ring => {
+                let mut peers = Vec::new();
+                if servers > 1 {
+                    let previous = (server + servers - 1) % servers;
+                    let next = (server + 1) % servers;
+                    peers.push(previous);
+                    if next != previous {
+                        peers.push(next);
+                    }
+                }
+                peers.sort();
+                peers.into_iter().map(Id::from).collect()
+            }
+            Topology::Line => {
+                let mut peers = Vec::new();
+                if server > 0 {
+                    peers.push(Id::from(server - 1));
+                }
+                if server + 1 < servers {
+                    peers.push(Id::from(server + 1));
+                }
+                peers
+            }
+            Topology::Custom(adjacency) => adjacency
+                .get(&server)
+                .map(|peers| peers.iter().map(|&p| Id::from(p)).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mesh" => Ok(Topology::Mesh),
+            "star" => Ok(Topology::Star),
+            "ring" => Ok(Topology::Ring),
+            "line" => Ok(Topology::Line),
+            adjacency => {
+                let parse_server = |server: &str| {
+                    server
+                        .trim()
+                        .parse::<usize>()
+                        .map_err(|e| format!("invalid server {:?}: {}", server, e))
+                };
+                let mut peers = BTreeMap::new();
+                for entry in adjacency.split(';').filter(|e| !e.trim().is_empty()) {
+                    let (server, server_peers) = entry.split_once(':').ok_or_else(|| {
+                        format!("invalid adjacency {:?}, expected `server:peer,peer`", entry)
+                    })?;
+                    let server_peers = server_peers
+                        .split(',')
+                        .filter(|p| !p.trim().is_empty())
+                        .map(parse_server)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    peers.insert(parse_server(server)?, server_peers);
+                }
+                for (server, server_peers) in &peers {
+                    for peer in server_peers {
+                        if !peers.get(peer).map_or(false, |p| p.contains(server)) {
+                            return Err(format!(
+                                "asymmetric adjacency, {} is a peer of {} but not the other way around",
+                                peer, server
+                            ));
+                        }
+                    }
+                }
+                Ok(Topology::Custom(peers))
+            }
+        }
+    }
+}
+
+impl Default for ModelOpts {
+    /// The same options as the defaults on the command line.
+    fn default() -> Self {
+        Self {
+            servers: 2,
+            relays: 0,
+            sync_method: SyncMethod::Changes,
+            batch_synchronisation: false,
+            out_of_order_changes: false,
+            faulty_peer: None,
+            restarts: false,
+            restart_mode: RestartMode::SaveLoad,
+            late_joiners: 0,
+            join_mode: JoinMode::Empty,
+            leavers: 0,
+            sync_state_resets: false,
+            sync_state_reset_mode: SyncStateResetMode::Drop,
+            partitions: false,
+            partition_cut: Vec::new(),
+            partition_mode: PartitionMode::Hold,
+            topology: Topology::Mesh,
+            network: NetworkType::Ordered,
+            actor_ids: ActorIds::Index,
+            initial_change_actor: 999,
+            observe_patches: false,
+            unproductive_sync_bound: None,
+            causal_history: false,
+            state_hashing: StateHashing::Exact,
+            symmetry: false,
+            checks: Vec::new(),
+            in_sync_check: false,
+            save_load_check: false,
+            historical_check: false,
+            error_free_check: false,
+        }
+    }
+}
+
 impl ModelOpts {
+    /// Names of the checks to enable, from the `--check` list and the individual flags, without
+    /// duplicates.
+    pub fn check_names(&self) -> Vec<String> {
+        let flags = [
+            (self.in_sync_check, "in-sync"),
+            (self.save_load_check, "save-load"),
+            (self.error_free_check, "error-free"),
+            (self.historical_check, "historical"),
+        ];
+        let mut names = flags
+            .into_iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, name)| name.to_owned())
+            .collect::<Vec<_>>();
+        for name in &self.checks {
+            let name = name.trim();
+            if !name.is_empty() && !names.iter().any(|n| n == name) {
+                names.push(name.to_owned());
+            }
+        }
+        names
+    }
+
     /// Create a model to use for checking.
     ///
     /// Intended for use in tests.
@@ -121,13 +429,118 @@ impl ModelOpts {
         println!("Built history: {:?}", history);
         let mut model = ActorModel::new(config.clone(), history);
 
-        // add servers
-        for i in 0..self.servers {
+        assert!(
+            !self.out_of_order_changes
+                || matches!(
+                    self.network,
+                    NetworkType::Unordered | NetworkType::Duplicating
+                ),
+            "out of order changes need an unordered network, got {:?}",
+            self.network
+        );
+
+        // only the messages sync method keeps sync states for peers
+        assert!(
+            !self.sync_state_resets || self.sync_method == SyncMethod::Messages,
+            "sync state resets need the messages sync method, got {:?}",
+            self.sync_method
+        );
+
+        let nodes = self.servers + self.relays;
+        if let Topology::Custom(adjacency) = &self.topology {
+            for (server, peers) in adjacency {
+                assert!(
+                    *server < nodes && peers.iter().all(|&p| p < nodes && p != *server),
+                    "invalid peers {:?} for server {} with {} servers and relays",
+                    peers,
+                    server,
+                    nodes
+                );
+            }
+        }
+        assert!(
+            self.partition_cut.iter().all(|&server| server < nodes),
+            "invalid partition cut {:?} with {} servers and relays",
+            self.partition_cut,
+            nodes
+        );
+
+        if self.symmetry {
+            assert!(
+                self.relays == 0
+                    && self.faulty_peer.is_none()
+                    && self.late_joiners == 0
+                    && self.leavers == 0
+                    && self.actor_ids == ActorIds::Index
+                    && self.topology == Topology::Mesh
+                    && self.partition_cut.is_empty(),
+                "symmetry needs servers that are configured the same, in a mesh"
+            );
+            // recorded histories refer to changes by hash, which can't be remapped per server
+            assert!(
+                !self.causal_history,
+                "symmetry can't be used with causal history"
+            );
+            let application = model_builder.application(0, &config);
+            let drivers = model_builder.drivers(0, &config);
+            for i in 1..self.servers {
+                assert!(
+                    model_builder.application(i, &config) == application
+                        && model_builder.drivers(i, &config) == drivers,
+                    "symmetry needs servers with the same application and drivers, server {} differs",
+                    i
+                );
+            }
+        }
+
+        // the last server is the faulty one, if any
+        let faulty_server = self
+            .faulty_peer
+            .and_then(|_| self.servers.checked_sub(1))
+            .map(Id::from);
+
+        // add servers, followed by relays
+        for i in 0..nodes {
+            let peers = self.topology.peers(i, self.servers, self.relays);
+            // without a cut each server is partitioned from all of its peers
+            let in_cut = |server: usize| self.partition_cut.contains(&server);
+            let partitioned_peers = peers
+                .iter()
+                .filter(|p| self.partition_cut.is_empty() || in_cut(usize::from(**p)) != in_cut(i))
+                .copied()
+                .collect();
+            let faulty_peers = peers
+                .iter()
+                .filter(|p| Some(**p) == faulty_server)
+                .copied()
+                .collect();
             model = model.actor(GlobalActor::Server(Server {
-                peers: model_peers(i, self.servers),
+                peers,
                 sync_method: self.sync_method,
                 batch_synchronisation: self.batch_synchronisation,
+                out_of_order_changes: self.out_of_order_changes,
                 restarts: self.restarts,
+                restart_mode: self.restart_mode,
+                partitions: self.partitions && (self.partition_cut.is_empty() || in_cut(i)),
+                partitioned_peers,
+                partition_mode: self.partition_mode,
+                lossy: self.network == NetworkType::Lossy,
+                joins_late: i < self.servers && i >= self.servers.saturating_sub(self.late_joiners),
+                join_mode: self.join_mode,
+                leaves: i < self.servers.min(self.leavers),
+                sync_state_resets: self.sync_state_resets,
+                sync_state_reset_mode: self.sync_state_reset_mode,
+                fault: self
+                    .faulty_peer
+                    .filter(|_| faulty_server == Some(Id::from(i))),
+                faulty_peers,
+                actor_ids: self.actor_ids,
+                initial_change_actor: self.initial_change_actor,
+                observe_patches: self.observe_patches,
+                unproductive_sync_bound: self.unproductive_sync_bound,
+                causal_history: self.causal_history,
+                state_hashing: self.state_hashing,
+                relay: i >= self.servers,
                 app: model_builder.application(i, &config),
             }))
         }
@@ -143,24 +556,158 @@ impl ModelOpts {
             }
         }
 
-        if self.in_sync_check {
-            model = properties::with_in_sync_check(model);
+        let mut checks = properties::builtin_checks();
+        for check in model_builder.checks() {
+            checks.register(check);
         }
-        if self.save_load_check {
-            model = properties::with_save_load_check(model);
-        }
-        if self.error_free_check {
-            model = properties::with_error_free_check(model);
+        let names = self.check_names();
+        // a failed eventually is only reported at states with nothing left to do, which timers
+        // that keep re-arming never reach
+        assert!(
+            !names.iter().any(|name| name == "convergence")
+                || !(self.batch_synchronisation
+                    || self.restarts
+                    || self.partitions
+                    || self.sync_state_resets),
+            "the convergence check can't be used with batch synchronisation, restarts, partitions or sync state resets as their timers never stop"
+        );
+        // without a bound livelocks would never be reported
+        assert!(
+            !names.iter().any(|name| name == "convergence")
+                || self.unproductive_sync_bound.is_some(),
+            "the convergence check needs an unproductive sync bound"
+        );
+        for name in &names {
+            if let Some(check) = checks.get(name) {
+                println!("Enabled check: {} ({:?} cost)", check.name, check.cost);
+            }
         }
+        model = checks
+            .apply(model, names.iter().map(String::as_str))
+            .unwrap_or_else(|e| panic!("{}", e));
 
         for property in model_builder.properties() {
             model = model.property(property.expectation, property.name, property.condition);
         }
         let record_request = model_builder.record_input();
         let record_response = model_builder.record_output();
-        model
+        model = model
             .record_msg_in(record_request)
-            .record_msg_out(record_response)
-            .init_network(Network::new_ordered(vec![]))
+            .record_msg_out(record_response);
+
+        match self.network {
+            // losses are modelled by the servers so the network itself stays ordered
+            NetworkType::Ordered | NetworkType::Lossy => {
+                model.init_network(Network::new_ordered(vec![]))
+            }
+            NetworkType::Unordered => {
+                model.init_network(Network::new_unordered_nonduplicating(vec![]))
+            }
+            NetworkType::Duplicating => {
+                model.init_network(Network::new_unordered_duplicating(vec![]))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[usize]) -> Vec<Id> {
+        ids.iter().copied().map(Id::from).collect()
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[clap(flatten)]
+        model_opts: ModelOpts,
+    }
+
+    #[test]
+    fn default_matches_empty_args() {
+        use clap::Parser;
+
+        assert_eq!(Cli::parse_from(["amc"]).model_opts, ModelOpts::default());
+    }
+
+    #[test]
+    fn parse_named_topologies() {
+        assert_eq!("mesh".parse(), Ok(Topology::Mesh));
+        assert_eq!("star".parse(), Ok(Topology::Star));
+        assert_eq!("ring".parse(), Ok(Topology::Ring));
+        assert_eq!("line".parse(), Ok(Topology::Line));
+    }
+
+    #[test]
+    fn parse_custom_topology() {
+        let expected = Topology::Custom(BTreeMap::from([
+            (0, vec![1]),
+            (1, vec![0, 2]),
+            (2, vec![1]),
+        ]));
+        assert_eq!("0:1;1:0,2;2:1".parse(), Ok(expected.clone()));
+        assert_eq!(" 0 : 1 ; 1 : 0 , 2 ; 2 : 1 ;".parse(), Ok(expected));
+        assert_eq!(
+            "0:;1:".parse(),
+            Ok(Topology::Custom(BTreeMap::from([(0, vec![]), (1, vec![])])))
+        );
+    }
+
+    #[test]
+    fn parse_invalid_topology() {
+        assert!("0-1".parse::<Topology>().is_err());
+        assert!("0:a;a:0".parse::<Topology>().is_err());
+        assert!("tree".parse::<Topology>().is_err());
+        // 1 doesn't list 0 back
+        assert!("0:1;1:".parse::<Topology>().is_err());
+        assert!("0:1".parse::<Topology>().is_err());
+    }
+
+    #[test]
+    fn mesh_peers() {
+        assert_eq!(Topology::Mesh.peers(0, 3, 0), ids(&[1, 2]));
+        assert_eq!(Topology::Mesh.peers(1, 2, 1), ids(&[0, 2]));
+    }
+
+    #[test]
+    fn star_peers() {
+        // the first server is the hub
+        assert_eq!(Topology::Star.peers(0, 3, 0), ids(&[1, 2]));
+        assert_eq!(Topology::Star.peers(2, 3, 0), ids(&[0]));
+        // the first relay is the hub
+        assert_eq!(Topology::Star.peers(3, 3, 1), ids(&[0, 1, 2]));
+        assert_eq!(Topology::Star.peers(0, 3, 1), ids(&[3]));
+        assert_eq!(Topology::Star.peers(4, 3, 2), ids(&[3]));
+    }
+
+    #[test]
+    fn ring_peers() {
+        assert_eq!(Topology::Ring.peers(0, 1, 0), ids(&[]));
+        assert_eq!(Topology::Ring.peers(0, 2, 0), ids(&[1]));
+        assert_eq!(Topology::Ring.peers(0, 3, 0), ids(&[1, 2]));
+        assert_eq!(Topology::Ring.peers(1, 3, 0), ids(&[0, 2]));
+        // relays sit in the ring after the servers
+        assert_eq!(Topology::Ring.peers(0, 3, 1), ids(&[1, 3]));
+        assert_eq!(Topology::Ring.peers(3, 3, 1), ids(&[0, 2]));
+    }
+
+    #[test]
+    fn line_peers() {
+        assert_eq!(Topology::Line.peers(0, 1, 0), ids(&[]));
+        assert_eq!(Topology::Line.peers(0, 3, 0), ids(&[1]));
+        assert_eq!(Topology::Line.peers(1, 3, 0), ids(&[0, 2]));
+        assert_eq!(Topology::Line.peers(2, 3, 0), ids(&[1]));
+        // relays extend the line after the servers
+        assert_eq!(Topology::Line.peers(2, 3, 1), ids(&[1, 3]));
+        assert_eq!(Topology::Line.peers(3, 3, 1), ids(&[2]));
+    }
+
+    #[test]
+    fn custom_peers() {
+        let topology = "0:1;1:0,2;2:1".parse::<Topology>().unwrap();
+        assert_eq!(topology.peers(0, 3, 0), ids(&[1]));
+        assert_eq!(topology.peers(1, 3, 0), ids(&[0, 2]));
+        assert_eq!(topology.peers(2, 3, 0), ids(&[1]));
     }
 }
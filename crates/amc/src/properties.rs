@@ -1,17 +1,164 @@
-use std::{fmt::Debug, hash::Hash, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    sync::Arc,
+};
 
 use automerge::Automerge;
 use stateright::actor::{ActorModel, ActorModelState};
 
-use crate::client::Application;
 use crate::client::DerefDocument;
+use crate::client::{Application, Specification};
 use crate::document::materialize;
 use crate::document::materialize_at;
+use crate::document::materialize_conflicts;
+use crate::document::Document;
 use crate::drive::Drive;
 use crate::global::GlobalMsg;
 use crate::global::{GlobalActor, GlobalActorState};
+use crate::history::causally_consistent;
 use crate::server::ServerMsg;
 
+/// How expensive a check is to evaluate on each state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CheckCost {
+    /// Only inspects the states of actors.
+    Cheap,
+    /// Materializes documents or walks their changes.
+    Moderate,
+    /// Saves, loads or rebuilds documents.
+    Expensive,
+}
+
+/// A named, reusable check that adds properties to a model.
+pub struct Check<M> {
+    /// Name to select the check by, such as `in-sync`.
+    pub name: &'static str,
+    /// How expensive the check is to evaluate.
+    pub cost: CheckCost,
+    /// Add the check's properties to the model.
+    pub add: fn(M) -> M,
+}
+
+impl<M> Clone for Check<M> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name,
+            cost: self.cost,
+            add: self.add,
+        }
+    }
+}
+
+impl<M> Debug for Check<M> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Check")
+            .field("name", &self.name)
+            .field("cost", &self.cost)
+            .finish()
+    }
+}
+
+/// A registry of checks that can be selected by name.
+#[derive(Debug)]
+pub struct Checks<M> {
+    checks: Vec<Check<M>>,
+}
+
+impl<M> Default for Checks<M> {
+    fn default() -> Self {
+        Self { checks: Vec::new() }
+    }
+}
+
+impl<M> Checks<M> {
+    /// Add a check, replacing any existing check with the same name.
+    pub fn register(&mut self, check: Check<M>) {
+        self.checks.retain(|c| c.name != check.name);
+        self.checks.push(check);
+    }
+
+    /// Get a check by name.
+    pub fn get(&self, name: &str) -> Option<&Check<M>> {
+        self.checks.iter().find(|c| c.name == name)
+    }
+
+    /// Iterate over the registered checks.
+    pub fn iter(&self) -> impl Iterator<Item = &Check<M>> {
+        self.checks.iter()
+    }
+
+    /// Add the named checks to the model, failing on the first unknown name.
+    pub fn apply<'a>(
+        &self,
+        mut model: M,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<M, String> {
+        for name in names {
+            let check = self.get(name).ok_or_else(|| {
+                format!(
+                    "unknown check {:?}, expected one of {:?}",
+                    name,
+                    self.checks.iter().map(|c| c.name).collect::<Vec<_>>()
+                )
+            })?;
+            model = (check.add)(model);
+        }
+        Ok(model)
+    }
+}
+
+/// The checks built into amc.
+pub fn builtin_checks<A, D, C, H>() -> Checks<ActorModel<GlobalActor<A, D>, C, H>>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    type Add<A, D, C, H> =
+        fn(ActorModel<GlobalActor<A, D>, C, H>) -> ActorModel<GlobalActor<A, D>, C, H>;
+    let mut checks = Checks::default();
+    let builtin: [(&'static str, CheckCost, Add<A, D, C, H>); 12] = [
+        ("in-sync", CheckCost::Cheap, with_in_sync_check),
+        ("save-load", CheckCost::Expensive, with_save_load_check),
+        (
+            "historical",
+            CheckCost::Expensive,
+            with_historical_document_check,
+        ),
+        (
+            "crash-recovery",
+            CheckCost::Moderate,
+            with_crash_recovery_check,
+        ),
+        (
+            "queued-changes",
+            CheckCost::Cheap,
+            with_queued_changes_check,
+        ),
+        ("conflicts", CheckCost::Moderate, with_conflicts_check),
+        (
+            "duplicate-actors",
+            CheckCost::Cheap,
+            with_duplicate_actors_check,
+        ),
+        ("patched-view", CheckCost::Moderate, with_patched_view_check),
+        ("error-free", CheckCost::Cheap, with_error_free_check),
+        (
+            "invalid-messages",
+            CheckCost::Cheap,
+            with_invalid_messages_check,
+        ),
+        ("convergence", CheckCost::Cheap, with_convergence_check),
+        ("causal", CheckCost::Moderate, with_causal_consistency_check),
+    ];
+    for (name, cost, add) in builtin {
+        checks.register(Check { name, cost, add });
+    }
+    checks
+}
+
 /// Add default properties to a model.
 ///
 /// These include checking for consistent states when syncing is completed, save and load
@@ -97,6 +244,391 @@ where
     )
 }
 
+/// Ensure that servers can decode and apply every message they receive.
+///
+/// Messages from faulty peers are counted too, unlike for the error free check, so this shows
+/// whether the corruption they inject is noticed.
+pub fn with_invalid_messages_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "no invalid messages received",
+        |_, state| {
+            state.actor_states.iter().all(|s| {
+                if let GlobalActorState::Server(s) = &**s {
+                    s.document().invalid_messages() == 0
+                } else {
+                    true
+                }
+            })
+        },
+    )
+}
+
+/// Ensure that servers recover changes they lost in a crash, or missed before joining, by syncing
+/// with their peers.
+///
+/// Once there are no messages left in the network, and none held back by partitions or lossy
+/// networks, all servers in it should have the same heads. Servers that synchronise in batches may
+/// still have syncing left to do so this isn't checked for them.
+pub fn with_crash_recovery_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "servers recover through sync when there are no messages left",
+        |model, state| {
+            let batch_synchronisation = model.actors.iter().any(|actor| match actor {
+                GlobalActor::Server(server) => server.batch_synchronisation,
+                GlobalActor::Client(_) => false,
+            });
+            // partitioned servers hold or drop messages until they heal and sync again, and
+            // servers on a lossy network hold messages until they process or lose them
+            let partitioned = state.actor_states.iter().any(|s| match &**s {
+                GlobalActorState::Server(s) => {
+                    s.document().is_partitioned() || s.document().has_lossy_messages()
+                }
+                GlobalActorState::Client(_) => false,
+            });
+            batch_synchronisation
+                || partitioned
+                || state.network.iter_deliverable().next().is_some()
+                || all_same_heads(&state.actor_states)
+        },
+    )
+}
+
+/// Ensure that no changes are left queued, waiting on their dependencies, when syncing is done.
+pub fn with_queued_changes_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "no queued changes when syncing is done",
+        |_, state| {
+            !syncing_done(state)
+                || state.actor_states.iter().all(|s| {
+                    if let GlobalActorState::Server(s) = &**s {
+                        !s.document().has_queued_changes()
+                    } else {
+                        true
+                    }
+                })
+        },
+    )
+}
+
+/// Ensure that all servers agree on the conflicting values in their documents when there is no
+/// syncing to be done.
+pub fn with_conflicts_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "same conflicts when syncing is done",
+        |_, state| !syncing_done(state) || all_same_conflicts(&state.actor_states),
+    )
+}
+
+/// Ensure that no two servers use the same actor id for their documents.
+pub fn with_duplicate_actors_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "no servers share an actor id",
+        |_, state| {
+            let mut actors = BTreeSet::new();
+            state.actor_states.iter().all(|s| {
+                if let GlobalActorState::Server(s) = &**s {
+                    actors.insert(s.document().get_actor().clone())
+                } else {
+                    true
+                }
+            })
+        },
+    )
+}
+
+/// Ensure that the views applications derive from patches always match the materialized document.
+///
+/// Applications that don't keep such a view are skipped.
+pub fn with_patched_view_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "patched view matches the document",
+        |model, state| {
+            model
+                .actors
+                .iter()
+                .zip(&state.actor_states)
+                .all(|(actor, s)| match (actor, &**s) {
+                    (GlobalActor::Server(server), GlobalActorState::Server(s)) => server
+                        .app
+                        .patched_view(s)
+                        .map_or(true, |view| view == materialize(s.document())),
+                    _ => true,
+                })
+        },
+    )
+}
+
+/// Ensure that servers eventually converge once drivers stop issuing inputs, and that syncing
+/// doesn't livelock.
+///
+/// Convergence is judged when the model runs out of new states to explore, by which point every
+/// message and timer has had the chance to fire, so this assumes a fair scheduling of syncing.
+/// Timers that keep re-arming mean there is always something left to do, so this can't be used
+/// with them.
+/// Livelocks never run out of states so are caught by servers receiving more sync messages in a row
+/// than `unproductive_sync_bound` without them changing their documents, so models with this check
+/// must set that bound.
+pub fn with_convergence_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model
+        .property(
+            stateright::Expectation::Eventually,
+            "servers converge once drivers are done",
+            |_, state| drivers_done(state) && all_same_heads(&state.actor_states),
+        )
+        .property(
+            stateright::Expectation::Always,
+            "syncing makes progress",
+            |_, state| joined_documents(&state.actor_states).all(|d| !d.is_sync_livelocked()),
+        )
+}
+
+/// Ensure that no input sees a change without also seeing its causal predecessors, including the
+/// effects of earlier inputs to the same server.
+///
+/// This checks the histories recorded with `causal_history`, passing trivially without them.
+pub fn with_causal_consistency_check<A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "inputs see causally consistent documents",
+        |_, state| {
+            causally_consistent(
+                state
+                    .actor_states
+                    .iter()
+                    .filter_map(|actor| match &**actor {
+                        GlobalActorState::Client(_) => None,
+                        GlobalActorState::Server(s) => s.document().causal_history(),
+                    }),
+            )
+        },
+    )
+}
+
+/// Ensure that converged documents match the specification `S` applied to some order of the
+/// inputs that respects causality.
+///
+/// This orders the inputs recorded with `causal_history`, passing trivially without them. Inputs
+/// whose changes were lost, such as in a crash, aren't included.
+///
+/// **Warning**: This tries the causal orders of the inputs in turn, skipping those that reach a
+/// state already tried with the same inputs placed, so is only suited to small numbers of inputs.
+pub fn with_refinement_check<S, A, D, C, H>(
+    model: ActorModel<GlobalActor<A, D>, C, H>,
+) -> ActorModel<GlobalActor<A, D>, C, H>
+where
+    S: Specification<A>,
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    model.property(
+        stateright::Expectation::Always,
+        "converged documents refine the specification",
+        |model, state| !syncing_done(state) || refines::<S, A, D, C, H>(model, state),
+    )
+}
+
+/// A check, named `refinement`, for converged documents matching the specification `S`.
+pub fn refinement_check<S, A, D, C, H>() -> Check<ActorModel<GlobalActor<A, D>, C, H>>
+where
+    S: Specification<A>,
+    A: Application,
+    D: Drive<A>,
+    H: Hash + Debug + Clone,
+{
+    Check {
+        name: "refinement",
+        cost: CheckCost::Expensive,
+        add: with_refinement_check::<S, A, D, C, H>,
+    }
+}
+
+fn refines<S, A, D, C, H>(
+    model: &ActorModel<GlobalActor<A, D>, C, H>,
+    state: &ActorModelState<GlobalActor<A, D>, H>,
+) -> bool
+where
+    S: Specification<A>,
+    A: Application,
+    D: Drive<A>,
+{
+    let app = model.actors.iter().find_map(|actor| match actor {
+        GlobalActor::Server(s) if !s.relay => Some(&s.app),
+        _ => None,
+    });
+    let document = joined_documents(&state.actor_states).next();
+    let (app, document) = match (app, document) {
+        (Some(app), Some(document)) => (app, document),
+        _ => return true,
+    };
+    let histories = state
+        .actor_states
+        .iter()
+        .filter_map(|actor| match &**actor {
+            GlobalActorState::Client(_) => None,
+            GlobalActorState::Server(s) => s.document().causal_history(),
+        })
+        .collect::<Vec<_>>();
+    if histories.is_empty() {
+        return true;
+    }
+
+    let changes = document
+        .get_changes(&[])
+        .unwrap_or_default()
+        .into_iter()
+        .map(|c| c.hash())
+        .collect::<BTreeSet<_>>();
+    // each operation along with the indices of the operations that must come before it
+    let mut operations = Vec::new();
+    for history in &histories {
+        let mut previous = None;
+        for operation in history.operations() {
+            if !operation.produced.iter().all(|h| changes.contains(h)) {
+                continue;
+            }
+            operations.push((operation, previous));
+            previous = Some(operations.len() - 1);
+        }
+    }
+    // operations come after the previous one on the same server and any whose changes they saw
+    let predecessors = (0..operations.len())
+        .map(|i| {
+            let (operation, previous) = operations[i];
+            (0..operations.len())
+                .filter(|&j| {
+                    Some(j) == previous
+                        || (j != i
+                            && operations[j]
+                                .0
+                                .produced
+                                .iter()
+                                .any(|h| operation.visible.contains(h)))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let expected = S::abstraction(document);
+    let mut placed = vec![false; operations.len()];
+    let mut failed = HashSet::new();
+    any_causal_order::<S, A>(
+        &operations
+            .iter()
+            .map(|(operation, _)| operation.input.downcast_ref::<A::Input>().unwrap())
+            .collect::<Vec<_>>(),
+        &predecessors,
+        &mut placed,
+        S::init(app),
+        &expected,
+        &mut failed,
+    )
+}
+
+/// Try placing each operation whose predecessors are placed, checking whether any complete order
+/// reaches the expected state.
+///
+/// Orders of the same operations that reach the same state finish the same way, so the placed
+/// operations and states that didn't lead to the expected state are kept in `failed` and not
+/// tried again.
+fn any_causal_order<S, A>(
+    inputs: &[&A::Input],
+    predecessors: &[Vec<usize>],
+    placed: &mut [bool],
+    state: S::State,
+    expected: &S::State,
+    failed: &mut HashSet<(Vec<bool>, S::State)>,
+) -> bool
+where
+    S: Specification<A>,
+    A: Application,
+{
+    if placed.iter().all(|p| *p) {
+        return state == *expected;
+    }
+    let tried = (placed.to_vec(), state);
+    if failed.contains(&tried) {
+        return false;
+    }
+    for i in 0..inputs.len() {
+        if placed[i] || !predecessors[i].iter().all(|&p| placed[p]) {
+            continue;
+        }
+        let mut next = tried.1.clone();
+        S::apply(&mut next, inputs[i]);
+        placed[i] = true;
+        let found = any_causal_order::<S, A>(inputs, predecessors, placed, next, expected, failed);
+        placed[i] = false;
+        if found {
+            return true;
+        }
+    }
+    failed.insert(tried);
+    false
+}
+
 /// Ensure that historical queries on the documents return the correct values.
 pub fn with_historical_document_check<A, D, C, H>(
     model: ActorModel<GlobalActor<A, D>, C, H>,
@@ -149,37 +681,54 @@ fn get_historical_documents(doc: &Automerge) -> Vec<Automerge> {
     documents
 }
 
-/// Check that all servers have the same document heads.
-pub fn all_same_heads<T, A>(actors: &[Arc<GlobalActorState<T, A>>]) -> bool
+/// Get the documents of the servers that are currently part of the network.
+///
+/// Servers that are yet to join or have left are skipped.
+pub fn joined_documents<T, A>(
+    actors: &[Arc<GlobalActorState<T, A>>],
+) -> impl Iterator<Item = &Document>
 where
     T: Drive<A>,
     A: Application,
 {
-    actors.windows(2).all(|w| match (&*w[0], &*w[1]) {
-        (GlobalActorState::Client(_), GlobalActorState::Client(_)) => true,
-        (GlobalActorState::Client(_), GlobalActorState::Server(_)) => true,
-        (GlobalActorState::Server(_), GlobalActorState::Client(_)) => true,
-        (GlobalActorState::Server(a), GlobalActorState::Server(b)) => {
-            a.document().get_heads() == b.document().get_heads()
-        }
+    actors.iter().filter_map(|actor| match &**actor {
+        GlobalActorState::Client(_) => None,
+        GlobalActorState::Server(s) => Some(s.document()).filter(|d| d.is_joined()),
     })
 }
 
+/// Check that all servers in the network have the same document heads.
+pub fn all_same_heads<T, A>(actors: &[Arc<GlobalActorState<T, A>>]) -> bool
+where
+    T: Drive<A>,
+    A: Application,
+{
+    let documents = joined_documents(actors).collect::<Vec<_>>();
+    documents
+        .windows(2)
+        .all(|w| w[0].get_heads() == w[1].get_heads())
+}
+
 fn all_same_state<T, A>(actors: &[Arc<GlobalActorState<T, A>>]) -> bool
 where
     T: Drive<A>,
     A: Application,
 {
-    actors.windows(2).all(|w| match (&*w[0], &*w[1]) {
-        (GlobalActorState::Client(_), GlobalActorState::Client(_)) => true,
-        (GlobalActorState::Client(_), GlobalActorState::Server(_)) => true,
-        (GlobalActorState::Server(_), GlobalActorState::Client(_)) => true,
-        (GlobalActorState::Server(a), GlobalActorState::Server(b)) => {
-            let a_vals = materialize(a.document());
-            let b_vals = materialize(b.document());
-            a_vals == b_vals
-        }
-    })
+    let documents = joined_documents(actors).collect::<Vec<_>>();
+    documents
+        .windows(2)
+        .all(|w| materialize(w[0]) == materialize(w[1]))
+}
+
+fn all_same_conflicts<T, A>(actors: &[Arc<GlobalActorState<T, A>>]) -> bool
+where
+    T: Drive<A>,
+    A: Application,
+{
+    let documents = joined_documents(actors).collect::<Vec<_>>();
+    documents
+        .windows(2)
+        .all(|w| materialize_conflicts(w[0]) == materialize_conflicts(w[1]))
 }
 
 /// Check whether syncing is complete at this time. That is, there are no sync messages pending
@@ -198,6 +747,9 @@ where
                 missing_changes_bytes: _,
             } => true,
             ServerMsg::SyncSaveLoadRaw { doc_bytes: _ } => true,
+            ServerMsg::SyncIncrementalSaveLoadRaw { chunk_bytes: _ } => true,
+            ServerMsg::Join => true,
+            ServerMsg::Leave => true,
         },
         GlobalMsg::ClientToServer(_) => false,
     });
@@ -205,6 +757,18 @@ where
     all_documents_same_heads && !network_contains_sync_messages
 }
 
+/// Check whether drivers have stopped issuing inputs, with no inputs or outputs in-flight.
+pub fn drivers_done<A, D, H>(state: &ActorModelState<GlobalActor<A, D>, H>) -> bool
+where
+    A: Application,
+    D: Drive<A>,
+{
+    !state
+        .network
+        .iter_deliverable()
+        .any(|e| matches!(e.msg, GlobalMsg::ClientToServer(_)))
+}
+
 fn syncing_done_and_in_sync<A, D, H>(state: &ActorModelState<GlobalActor<A, D>, H>) -> bool
 where
     A: Application,
@@ -1,16 +1,20 @@
 use std::collections::BTreeMap;
+use std::fmt::Write;
 use std::hash::Hash;
 use std::time::Duration;
 use std::time::Instant;
 
 use num_format::SystemLocale;
 use num_format::ToFormattedString;
-use stateright::actor::ActorModel;
+use stateright::actor::{ActorModel, ActorModelState};
 use stateright::Expectation;
 use stateright::Model;
 
+use crate::client::Application;
+use crate::drive::Drive;
 use crate::global::GlobalActor;
 use crate::model::ModelBuilder;
+use crate::properties::joined_documents;
 
 /// A reporter with more information about the rate of new states being processed.
 #[derive(Debug, Default)]
@@ -19,6 +23,8 @@ pub struct Reporter {
     last_unique: usize,
     last_report: Option<Instant>,
     properties: BTreeMap<&'static str, Expectation>,
+    /// Output kept rather than printed, when capturing it.
+    captured: Option<String>,
 }
 
 impl Reporter {
@@ -36,13 +42,37 @@ impl Reporter {
             last_unique: 0,
             last_report: None,
             properties,
+            captured: None,
+        }
+    }
+
+    /// Keep the output rather than printing it, such as to inspect it in tests.
+    pub fn capture(mut self) -> Self {
+        self.captured = Some(String::new());
+        self
+    }
+
+    /// Get the output kept so far, if capturing it.
+    pub fn captured(&self) -> Option<&str> {
+        self.captured.as_deref()
+    }
+
+    /// Print a line of output, or keep it when capturing.
+    fn output(&mut self, line: std::fmt::Arguments) {
+        match &mut self.captured {
+            Some(captured) => {
+                let _ = writeln!(captured, "{}", line);
+            }
+            None => println!("{}", line),
         }
     }
 }
 
-impl<M> stateright::report::Reporter<M> for Reporter
+impl<A, D, C, H> stateright::report::Reporter<ActorModel<GlobalActor<A, D>, C, H>> for Reporter
 where
-    M: Model,
+    ActorModel<GlobalActor<A, D>, C, H>: Model<State = ActorModelState<GlobalActor<A, D>, H>>,
+    A: Application,
+    D: Drive<A>,
 {
     fn report_checking(&mut self, data: stateright::report::ReportData) {
         if !data.done {
@@ -63,7 +93,7 @@ where
         let status = if data.done { "Done    " } else { "Checking" };
         let locale = SystemLocale::default().unwrap();
         let duration = data.duration.as_millis();
-        println!(
+        self.output(format_args!(
             "{} states={} (+{} {}/s), unique={} (+{} {}/s), max_depth={}, duration={:?}ms",
             status,
             data.total_states.to_formatted_string(&locale),
@@ -74,7 +104,7 @@ where
             unique_rate.to_formatted_string(&locale),
             max_depth,
             duration
-        );
+        ));
 
         self.last_total = data.total_states;
         self.last_unique = data.unique_states;
@@ -82,11 +112,14 @@ where
 
     fn report_discoveries(
         &mut self,
-        discoveries: BTreeMap<&'static str, stateright::report::ReportDiscovery<M>>,
+        discoveries: BTreeMap<
+            &'static str,
+            stateright::report::ReportDiscovery<ActorModel<GlobalActor<A, D>, C, H>>,
+        >,
     ) where
-        <M as Model>::Action: std::fmt::Debug,
-        <M as Model>::State: std::fmt::Debug,
-        <M as Model>::State: Hash,
+        <ActorModel<GlobalActor<A, D>, C, H> as Model>::Action: std::fmt::Debug,
+        <ActorModel<GlobalActor<A, D>, C, H> as Model>::State: std::fmt::Debug,
+        <ActorModel<GlobalActor<A, D>, C, H> as Model>::State: Hash,
     {
         let discoveries: BTreeMap<_, _> = discoveries.into_iter().collect();
         let (success, failure): (Vec<_>, Vec<_>) =
@@ -94,27 +127,35 @@ where
                 property_holds(expectation, discoveries.get(*name).is_some())
             });
 
-        for (name, expectation) in &self.properties {
-            let status = if property_holds(expectation, discoveries.get(name).is_some()) {
-                "OK"
-            } else {
-                "FAILED"
-            };
-            println!("Property {:?} {:?} {}", expectation, name, status);
+        let (success, failure) = (success.len(), failure.len());
+        for (name, expectation) in self.properties.clone() {
+            let holds = property_holds(&expectation, discoveries.get(name).is_some());
+            let status = if holds { "OK" } else { "FAILED" };
+            self.output(format_args!(
+                "Property {:?} {:?} {}",
+                expectation, name, status
+            ));
             if let Some(discovery) = discoveries.get(name) {
-                print!("{}, {}", discovery.classification, discovery.path,);
-                println!(
+                // the path ends with a newline already
+                let path = format!("{}, {}", discovery.classification, discovery.path);
+                self.output(format_args!("{}", path.trim_end()));
+                if !holds {
+                    // only counterexamples end in documents worth comparing
+                    for diff in document_diffs(discovery.path.last_state()) {
+                        self.output(format_args!("{}", diff));
+                    }
+                }
+                self.output(format_args!(
                     "To explore this path try re-running with `explore {}`",
                     discovery.path.encode()
-                );
+                ));
             }
         }
 
-        println!(
+        self.output(format_args!(
             "Properties checked. {} succeeded, {} failed",
-            success.len(),
-            failure.len()
-        );
+            success, failure
+        ));
     }
 
     fn delay(&self) -> std::time::Duration {
@@ -122,6 +163,32 @@ where
     }
 }
 
+/// Describe how the documents of servers in the network differ from the first one's.
+fn document_diffs<A, D, H>(state: &ActorModelState<GlobalActor<A, D>, H>) -> Vec<String>
+where
+    A: Application,
+    D: Drive<A>,
+{
+    let mut documents = joined_documents(&state.actor_states);
+    let first = match documents.next() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    documents
+        .filter_map(|document| {
+            let diff = first.diff(document);
+            (!diff.is_empty()).then(|| {
+                format!(
+                    "Document of actor {} differs from actor {}:\n{}",
+                    document.get_actor(),
+                    first.get_actor(),
+                    diff
+                )
+            })
+        })
+        .collect()
+}
+
 fn property_holds(expectation: &Expectation, discovery: bool) -> bool {
     match (expectation, discovery) {
         // counter-example
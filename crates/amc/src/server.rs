@@ -7,11 +7,15 @@ use crate::bytes::Bytes;
 use crate::client::Application;
 use crate::client::ApplicationMsg;
 use crate::client::DerefDocument;
+use crate::document::{ActorIds, Document, Membership, StateHashing};
 use crate::global::GlobalMsg;
 use crate::global::GlobalTimer;
+use crate::history::RecordedInput;
 use automerge::sync;
+use automerge::ActorId;
 use automerge::Automerge;
 use automerge::Change;
+use automerge::ChangeHash;
 use stateright::actor::model_timeout;
 use stateright::actor::Actor;
 use stateright::actor::Id;
@@ -32,8 +36,57 @@ pub struct Server<A> {
     ///
     /// **Warning**: this can be very expensive.
     pub batch_synchronisation: bool,
+    /// Whether to send each change in its own message when syncing changes, so that changes can
+    /// be delivered before their dependencies.
+    pub out_of_order_changes: bool,
     /// Whether to trigger restarts in the application.
     pub restarts: bool,
+    /// How the application restarts.
+    pub restart_mode: RestartMode,
+    /// Whether to partition this server from its peers, and later heal it.
+    pub partitions: bool,
+    /// Peers on the other side of the partition cut, that messages are held back from or dropped
+    /// for while partitioned.
+    pub partitioned_peers: Vec<Id>,
+    /// What happens to messages that would cross a partition.
+    pub partition_mode: PartitionMode,
+    /// Whether messages from peers can be lost, each being held on arrival until it is either
+    /// processed or dropped.
+    pub lossy: bool,
+    /// Whether this server joins the network some time after starting.
+    pub joins_late: bool,
+    /// What this server starts from when it joins late.
+    pub join_mode: JoinMode,
+    /// Whether this server leaves the network permanently at some point.
+    pub leaves: bool,
+    /// Whether this server forgets its sync state for a peer at times, while the peer keeps its
+    /// own.
+    pub sync_state_resets: bool,
+    /// How the sync state for a peer is forgotten.
+    pub sync_state_reset_mode: SyncStateResetMode,
+    /// Fault to inject into the payloads this server sends, making it a faulty peer.
+    pub fault: Option<Fault>,
+    /// Peers that are faulty, so payloads from them that can't be applied are expected rather
+    /// than errors.
+    pub faulty_peers: Vec<Id>,
+    /// How this server picks the actor id for its document.
+    pub actor_ids: ActorIds,
+    /// Actor to use for the initial change of the document.
+    pub initial_change_actor: u64,
+    /// Whether this server's document observes patches, delivering them to the application.
+    pub observe_patches: bool,
+    /// Number of sync messages in a row that don't change this server's document before syncing
+    /// is considered livelocked, `None` to not track it.
+    pub unproductive_sync_bound: Option<usize>,
+    /// Whether to record the changes each input made and could see, for checking causal
+    /// consistency.
+    pub causal_history: bool,
+    /// How this server's document is hashed when deduplicating states.
+    pub state_hashing: StateHashing,
+    /// Whether this server is a relay, forwarding what it receives to its other peers.
+    ///
+    /// Relays have no drivers so never run any application logic.
+    pub relay: bool,
     /// Application logic.
     pub app: A,
 }
@@ -47,6 +100,69 @@ pub enum SyncMethod {
     Messages,
     /// Save the current document and send its entirety to peers for merging.
     SaveLoad,
+    /// Save the changes since the last sync incrementally and send them to peers for loading.
+    IncrementalSaveLoad,
+}
+
+/// Ways that servers can restart.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum RestartMode {
+    /// Save the document and load it again, no data is lost.
+    SaveLoad,
+    /// Save the document incrementally and rebuild it from the concatenated chunks, no data is
+    /// lost.
+    IncrementalSaveLoad,
+    /// Crash, rolling the document back to the last time it was persisted.
+    Crash,
+}
+
+/// What happens to messages that would cross a partition.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum PartitionMode {
+    /// Hold messages until the partition heals and then deliver them.
+    Hold,
+    /// Drop messages, they are never delivered.
+    ///
+    /// Once the partition heals, a server that dropped messages sends its changes since the
+    /// partition started again and asks the peers it was cut off from to sync everything, as for
+    /// a server joining.
+    Drop,
+}
+
+/// How a server forgets its sync state for a peer.
+///
+/// Sync states are only used when syncing with [`SyncMethod::Messages`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum SyncStateResetMode {
+    /// Remove the sync state, as if the peer had never been synced with.
+    Drop,
+    /// Roll the sync state back to a snapshot saved earlier, as if it was restored from stale
+    /// storage, or remove it if no snapshot has been saved.
+    Stale,
+}
+
+/// What a server that joins late starts from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum JoinMode {
+    /// Start from the document it has and rely on syncing to get the rest.
+    ///
+    /// Peers answer a join by syncing their whole history with the server, using the sync method
+    /// of the model.
+    Empty,
+    /// Peers send a snapshot of their document to the server when it joins.
+    Snapshot,
+}
+
+/// Ways that a faulty peer corrupts the payloads it sends.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, clap::ValueEnum)]
+pub enum Fault {
+    /// Truncate payloads to half of their length.
+    Truncate,
+    /// Flip a bit in the middle of payloads.
+    BitFlip,
+    /// Replay the previous payload sent to a peer after each later one, so the peer receives
+    /// stale data.
+    Replay,
 }
 
 /// Messages that servers send to each other.
@@ -67,12 +183,58 @@ pub enum ServerMsg {
         /// Bytes of the saved document.
         doc_bytes: Bytes,
     },
+    /// An incrementally saved chunk of a document.
+    SyncIncrementalSaveLoadRaw {
+        /// Bytes of the incremental chunk.
+        chunk_bytes: Bytes,
+    },
+    /// The sending server has joined the network.
+    Join,
+    /// The sending server has left the network.
+    Leave,
+}
+
+impl ServerMsg {
+    /// Apply a function to each of the payloads in this message.
+    fn map_payloads(self, f: impl Fn(Bytes) -> Bytes) -> Self {
+        match self {
+            ServerMsg::SyncChangeRaw {
+                missing_changes_bytes,
+            } => ServerMsg::SyncChangeRaw {
+                missing_changes_bytes: missing_changes_bytes.into_iter().map(f).collect(),
+            },
+            ServerMsg::SyncMessageRaw { message_bytes } => ServerMsg::SyncMessageRaw {
+                message_bytes: f(message_bytes),
+            },
+            ServerMsg::SyncSaveLoadRaw { doc_bytes } => ServerMsg::SyncSaveLoadRaw {
+                doc_bytes: f(doc_bytes),
+            },
+            ServerMsg::SyncIncrementalSaveLoadRaw { chunk_bytes } => {
+                ServerMsg::SyncIncrementalSaveLoadRaw {
+                    chunk_bytes: f(chunk_bytes),
+                }
+            }
+            ServerMsg::Join => ServerMsg::Join,
+            ServerMsg::Leave => ServerMsg::Leave,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Timer {
     Synchronise,
     Restart,
+    Persist,
+    Partition,
+    Heal,
+    Join,
+    Leave,
+    ResetSyncState(usize),
+    SnapshotSyncState(usize),
+    /// Process the oldest message held from a peer on a lossy network.
+    DeliverMessage(usize),
+    /// Lose the oldest message held from a peer on a lossy network.
+    LoseMessage(usize),
 }
 
 impl<A: Application> Actor for Server<A> {
@@ -91,7 +253,56 @@ impl<A: Application> Actor for Server<A> {
         if self.restarts {
             o.set_timer(GlobalTimer::Server(Timer::Restart), model_timeout());
         }
-        self.app.init(usize::from(id))
+        if self.partitions {
+            o.set_timer(GlobalTimer::Server(Timer::Partition), model_timeout());
+        }
+        if self.sync_state_resets {
+            for peer in &self.peers {
+                o.set_timer(
+                    GlobalTimer::Server(Timer::ResetSyncState(usize::from(*peer))),
+                    model_timeout(),
+                );
+                if self.sync_state_reset_mode == SyncStateResetMode::Stale {
+                    o.set_timer(
+                        GlobalTimer::Server(Timer::SnapshotSyncState(usize::from(*peer))),
+                        model_timeout(),
+                    );
+                }
+            }
+        }
+        let document = Document::with_actors(
+            self.actor_ids.actor_id(usize::from(id)),
+            ActorId::from(self.initial_change_actor.to_be_bytes()),
+        );
+        let mut state = self.app.init(usize::from(id), document);
+        state.document_mut().set_state_hashing(self.state_hashing);
+        if self.observe_patches {
+            state.document_mut().observe_patches();
+            // the application builds its view from the document it was initialised with
+            let patches = state.document().reload_patches();
+            let mut cow = Cow::Owned(state);
+            self.app.on_patches(&mut cow, true, patches);
+            state = cow.into_owned();
+        }
+        if let Some(bound) = self.unproductive_sync_bound {
+            state.document_mut().track_sync_progress(bound);
+        }
+        if self.causal_history {
+            state.document_mut().record_causal_history();
+        }
+        if self.restarts && self.restart_mode == RestartMode::Crash {
+            // the initial state of the application is always persisted
+            state.document_mut().persist();
+            o.set_timer(GlobalTimer::Server(Timer::Persist), model_timeout());
+        }
+        if self.joins_late {
+            state.document_mut().set_membership(Membership::Joining);
+            o.set_timer(GlobalTimer::Server(Timer::Join), model_timeout());
+        }
+        if self.leaves {
+            o.set_timer(GlobalTimer::Server(Timer::Leave), model_timeout());
+        }
+        state
     }
 
     /// Process a message from another peer or client.
@@ -103,9 +314,27 @@ impl<A: Application> Actor for Server<A> {
         msg: Self::Msg,
         o: &mut Out<Self>,
     ) {
+        if state.document().has_left() {
+            // we've gone, never to return
+            return;
+        }
         match msg {
             GlobalMsg::ClientToServer(ApplicationMsg::Input(request)) => {
+                let recorded = self
+                    .causal_history
+                    .then(|| (RecordedInput::new(&request), state.document().get_heads()));
                 let output = self.app.execute(state, request);
+                if let Some((input, heads_before)) = recorded {
+                    state
+                        .to_mut()
+                        .document_mut()
+                        .record_operation(input, &heads_before);
+                }
+                self.deliver_patches(state, false);
+                if self.unproductive_sync_bound.is_some() {
+                    // new local changes start a new round of syncing
+                    state.to_mut().document_mut().reset_sync_progress();
+                }
                 if let Some(output) = output {
                     o.send(
                         src,
@@ -117,33 +346,26 @@ impl<A: Application> Actor for Server<A> {
                     self.sync(state, o)
                 }
             }
-            GlobalMsg::ServerToServer(ServerMsg::SyncMessageRaw { message_bytes }) => {
-                let message = sync::Message::decode(&message_bytes.0).unwrap();
-                let document = state.to_mut().document_mut();
-                // receive the sync message
-                document.receive_sync_message(src.into(), message);
-                // try and generate a reply
-                if let Some(message) = document.generate_sync_message(src.into()) {
-                    o.send(
-                        src,
-                        GlobalMsg::ServerToServer(ServerMsg::SyncMessageRaw {
-                            message_bytes: Bytes(message.encode()),
-                        }),
-                    )
-                }
+            GlobalMsg::ServerToServer(_) if !state.document().is_joined() => {
+                // we aren't part of the network yet so nothing reaches us
             }
-            GlobalMsg::ServerToServer(ServerMsg::SyncChangeRaw {
-                missing_changes_bytes,
-            }) => {
-                for change_bytes in missing_changes_bytes {
-                    let change = Change::from_bytes(change_bytes.0).unwrap();
-                    state.to_mut().document_mut().apply_change(change)
+            GlobalMsg::ServerToServer(msg) => {
+                if state.document().is_partitioned() && self.partitioned_peers.contains(&src) {
+                    // the message crossed the partition
+                    match self.partition_mode {
+                        PartitionMode::Hold => {
+                            state.to_mut().document_mut().hold_inbound(src.into(), msg)
+                        }
+                        PartitionMode::Drop => state.to_mut().document_mut().drop_message(),
+                    }
+                } else if self.lossy {
+                    // the message may yet be lost, so wait to see whether it is processed
+                    state.to_mut().document_mut().receive_lossy(src.into(), msg);
+                    self.set_lossy_timers(usize::from(src), o);
+                } else {
+                    self.handle_server_msg(state, src, msg, o)
                 }
             }
-            GlobalMsg::ServerToServer(ServerMsg::SyncSaveLoadRaw { doc_bytes }) => {
-                let mut other_doc = Automerge::load(&doc_bytes.0).unwrap();
-                state.to_mut().document_mut().merge(&mut other_doc);
-            }
             GlobalMsg::ClientToServer(ApplicationMsg::Output(_)) => {
                 // we shouldn't be receiving responses
                 unreachable!()
@@ -159,6 +381,10 @@ impl<A: Application> Actor for Server<A> {
         timer: &Self::Timer,
         o: &mut Out<Self>,
     ) {
+        if state.document().has_left() {
+            // we've gone so let our timers lapse
+            return;
+        }
         match timer {
             GlobalTimer::Server(Timer::Synchronise) => {
                 o.set_timer(
@@ -172,15 +398,425 @@ impl<A: Application> Actor for Server<A> {
                     timer.clone(),
                     Duration::from_secs(1)..Duration::from_secs(2),
                 );
-                self.restart(state)
+                self.restart(state, o)
+            }
+            GlobalTimer::Server(Timer::Persist) => {
+                o.set_timer(
+                    timer.clone(),
+                    Duration::from_secs(1)..Duration::from_secs(2),
+                );
+                state.to_mut().document_mut().persist();
+            }
+            GlobalTimer::Server(Timer::Partition) => {
+                o.set_timer(
+                    GlobalTimer::Server(Timer::Heal),
+                    Duration::from_secs(1)..Duration::from_secs(2),
+                );
+                state.to_mut().document_mut().partition();
+            }
+            GlobalTimer::Server(Timer::Heal) => {
+                o.set_timer(
+                    GlobalTimer::Server(Timer::Partition),
+                    Duration::from_secs(1)..Duration::from_secs(2),
+                );
+                self.heal(state, o)
+            }
+            GlobalTimer::Server(Timer::Join) => {
+                state
+                    .to_mut()
+                    .document_mut()
+                    .set_membership(Membership::Joined);
+                self.broadcast_to_peers(state, ServerMsg::Join, o);
+                // share anything we did before joining
+                self.sync(state, o)
+            }
+            GlobalTimer::Server(Timer::Leave) => {
+                self.broadcast_to_peers(state, ServerMsg::Leave, o);
+                state
+                    .to_mut()
+                    .document_mut()
+                    .set_membership(Membership::Left);
+            }
+            GlobalTimer::Server(Timer::ResetSyncState(peer)) => {
+                o.set_timer(
+                    timer.clone(),
+                    Duration::from_secs(1)..Duration::from_secs(2),
+                );
+                let document = state.to_mut().document_mut();
+                match self.sync_state_reset_mode {
+                    SyncStateResetMode::Drop => document.remove_sync_state(*peer),
+                    SyncStateResetMode::Stale => document.restore_sync_state(*peer),
+                }
+            }
+            GlobalTimer::Server(Timer::SnapshotSyncState(peer)) => {
+                o.set_timer(
+                    timer.clone(),
+                    Duration::from_secs(1)..Duration::from_secs(2),
+                );
+                state.to_mut().document_mut().snapshot_sync_state(*peer);
+            }
+            GlobalTimer::Server(Timer::DeliverMessage(peer)) => {
+                if let Some(msg) = state.to_mut().document_mut().take_lossy(*peer) {
+                    self.handle_server_msg(state, Id::from(*peer), msg, o);
+                }
+                if state.document().has_lossy(*peer) {
+                    self.set_lossy_timers(*peer, o);
+                }
+            }
+            GlobalTimer::Server(Timer::LoseMessage(peer)) => {
+                state.to_mut().document_mut().take_lossy(*peer);
+                if state.document().has_lossy(*peer) {
+                    self.set_lossy_timers(*peer, o);
+                }
             }
         }
     }
 }
 
 impl<A: Application> Server<A> {
+    /// Process a message from another server.
+    fn handle_server_msg(
+        &self,
+        state: &mut Cow<<Self as Actor>::State>,
+        src: Id,
+        msg: ServerMsg,
+        o: &mut Out<Self>,
+    ) {
+        let heads_before = self.relay.then(|| state.document().get_heads());
+        let invalid_messages_before = state.document().invalid_messages();
+        match msg {
+            ServerMsg::SyncMessageRaw { message_bytes } => {
+                let message = match sync::Message::decode(&message_bytes.0) {
+                    Ok(message) => message,
+                    Err(_error) => {
+                        state.to_mut().document_mut().record_invalid_message();
+                        self.check_invalid_messages(state, src, invalid_messages_before);
+                        return;
+                    }
+                };
+                let document = state.to_mut().document_mut();
+                let progress_heads = self.unproductive_sync_bound.map(|_| document.get_heads());
+                // receive the sync message
+                document.receive_sync_message(src.into(), message);
+                if let Some(heads) = progress_heads {
+                    let changed = document.get_heads() != heads;
+                    document.record_sync_progress(src.into(), changed);
+                }
+                // try and generate a reply
+                if let Some(message) = document.generate_sync_message(src.into()) {
+                    self.send_to_peer(
+                        state,
+                        src,
+                        ServerMsg::SyncMessageRaw {
+                            message_bytes: Bytes(message.encode()),
+                        },
+                        o,
+                    )
+                }
+            }
+            ServerMsg::SyncChangeRaw {
+                missing_changes_bytes,
+            } => {
+                for change_bytes in missing_changes_bytes {
+                    match Change::from_bytes(change_bytes.0) {
+                        Ok(change) => state.to_mut().document_mut().apply_change(change),
+                        Err(_error) => state.to_mut().document_mut().record_invalid_message(),
+                    }
+                }
+            }
+            ServerMsg::SyncSaveLoadRaw { doc_bytes } => match Automerge::load(&doc_bytes.0) {
+                Ok(mut other_doc) => state.to_mut().document_mut().merge(&mut other_doc),
+                Err(_error) => state.to_mut().document_mut().record_invalid_message(),
+            },
+            ServerMsg::SyncIncrementalSaveLoadRaw { chunk_bytes } => {
+                state
+                    .to_mut()
+                    .document_mut()
+                    .load_incremental(&chunk_bytes.0);
+            }
+            ServerMsg::Join => {
+                // anything we thought we knew about the peer is stale now
+                state.to_mut().document_mut().remove_sync_state(src.into());
+                match self.join_mode {
+                    JoinMode::Snapshot => {
+                        let bytes = state.to_mut().document_mut().save();
+                        self.send_to_peer(
+                            state,
+                            src,
+                            ServerMsg::SyncSaveLoadRaw {
+                                doc_bytes: Bytes(bytes),
+                            },
+                            o,
+                        )
+                    }
+                    JoinMode::Empty => match self.sync_method {
+                        SyncMethod::Changes => {
+                            // the server missed our earlier changes, so send them all
+                            let missing_changes_bytes = state
+                                .document()
+                                .get_changes(&[])
+                                .unwrap()
+                                .into_iter()
+                                .map(|c| Bytes(c.raw_bytes().to_vec()))
+                                .collect::<Vec<_>>();
+                            self.send_to_peer(
+                                state,
+                                src,
+                                ServerMsg::SyncChangeRaw {
+                                    missing_changes_bytes,
+                                },
+                                o,
+                            )
+                        }
+                        SyncMethod::Messages => {
+                            if let Some(message) = state
+                                .to_mut()
+                                .document_mut()
+                                .generate_sync_message(src.into())
+                            {
+                                self.send_to_peer(
+                                    state,
+                                    src,
+                                    ServerMsg::SyncMessageRaw {
+                                        message_bytes: Bytes(message.encode()),
+                                    },
+                                    o,
+                                )
+                            }
+                        }
+                        SyncMethod::SaveLoad => {
+                            let bytes = state.to_mut().document_mut().save();
+                            self.send_to_peer(
+                                state,
+                                src,
+                                ServerMsg::SyncSaveLoadRaw {
+                                    doc_bytes: Bytes(bytes),
+                                },
+                                o,
+                            )
+                        }
+                        SyncMethod::IncrementalSaveLoad => {
+                            let bytes = state.document().save_incremental_after(&[]);
+                            self.send_to_peer(
+                                state,
+                                src,
+                                ServerMsg::SyncIncrementalSaveLoadRaw {
+                                    chunk_bytes: Bytes(bytes),
+                                },
+                                o,
+                            )
+                        }
+                    },
+                }
+            }
+            ServerMsg::Leave => {
+                state.to_mut().document_mut().set_departed(src.into());
+            }
+        }
+        self.check_invalid_messages(state, src, invalid_messages_before);
+        self.deliver_patches(state, false);
+        if let Some(heads_before) = heads_before {
+            self.forward(state, src, &heads_before, o)
+        }
+    }
+
+    /// Set the timers for processing or losing the oldest message held from a peer.
+    ///
+    /// Timers left over after the messages are gone do nothing when they fire.
+    fn set_lossy_timers(&self, peer: usize, o: &mut Out<Self>) {
+        o.set_timer(
+            GlobalTimer::Server(Timer::DeliverMessage(peer)),
+            model_timeout(),
+        );
+        o.set_timer(
+            GlobalTimer::Server(Timer::LoseMessage(peer)),
+            model_timeout(),
+        );
+    }
+
+    /// Mark the document as errored if a message from a peer that isn't faulty couldn't be
+    /// decoded or applied, as that is a failure in syncing rather than an injected fault.
+    fn check_invalid_messages(
+        &self,
+        state: &mut Cow<<Self as Actor>::State>,
+        src: Id,
+        invalid_messages_before: usize,
+    ) {
+        if state.document().invalid_messages() > invalid_messages_before
+            && !self.faulty_peers.contains(&src)
+        {
+            state.to_mut().document_mut().set_error();
+        }
+    }
+
+    /// Deliver patches from changes to the document to the application, if it is observing them.
+    ///
+    /// When `reset` is set the document has been replaced so the patches build it from empty.
+    fn deliver_patches(&self, state: &mut Cow<<Self as Actor>::State>, reset: bool) {
+        if !state.document().is_observing_patches() {
+            return;
+        }
+        let patches = if reset {
+            let document = state.to_mut().document_mut();
+            // anything pending is superseded by the reload
+            document.take_patches();
+            document.reload_patches()
+        } else {
+            state.to_mut().document_mut().take_patches()
+        };
+        if reset || !patches.is_empty() {
+            self.app.on_patches(state, reset, patches);
+        }
+    }
+
+    /// Forward anything new that a relay received from `src` on to its other peers.
+    fn forward(
+        &self,
+        state: &mut Cow<<Self as Actor>::State>,
+        src: Id,
+        heads_before: &[ChangeHash],
+        o: &mut Out<Self>,
+    ) {
+        match &self.sync_method {
+            SyncMethod::Changes => {
+                // relays don't make changes of their own so forward the ones that are new to us
+                let new_changes = state
+                    .document()
+                    .get_changes(heads_before)
+                    .unwrap()
+                    .into_iter()
+                    .map(|c| Bytes(c.raw_bytes().to_vec()))
+                    .collect::<Vec<_>>();
+                if !new_changes.is_empty() {
+                    for peer in self.peers.iter().filter(|&&p| p != src) {
+                        self.send_to_peer(
+                            state,
+                            *peer,
+                            ServerMsg::SyncChangeRaw {
+                                missing_changes_bytes: new_changes.clone(),
+                            },
+                            o,
+                        );
+                    }
+                }
+            }
+            SyncMethod::Messages => {
+                // we've already replied to the source, so try and sync with everyone else
+                for peer in self.peers.iter().filter(|&&p| p != src) {
+                    if let Some(message) = state
+                        .to_mut()
+                        .document_mut()
+                        .generate_sync_message((*peer).into())
+                    {
+                        self.send_to_peer(
+                            state,
+                            *peer,
+                            ServerMsg::SyncMessageRaw {
+                                message_bytes: Bytes(message.encode()),
+                            },
+                            o,
+                        )
+                    }
+                }
+            }
+            SyncMethod::SaveLoad | SyncMethod::IncrementalSaveLoad => {
+                if state.document().get_heads() != heads_before {
+                    self.sync(state, o)
+                }
+            }
+        }
+    }
+
+    /// Send a message to a peer, unless we are partitioned from it or it has left.
+    fn send_to_peer(
+        &self,
+        state: &mut Cow<<Self as Actor>::State>,
+        peer: Id,
+        msg: ServerMsg,
+        o: &mut Out<Self>,
+    ) {
+        if !state.document().is_joined() {
+            // we aren't part of the network so can't reach anyone
+        } else if state.document().has_departed(peer.into()) {
+            // the peer has gone so there is nobody to receive it
+        } else if state.document().is_partitioned() && self.partitioned_peers.contains(&peer) {
+            match self.partition_mode {
+                PartitionMode::Hold => state
+                    .to_mut()
+                    .document_mut()
+                    .hold_outbound(peer.into(), msg),
+                PartitionMode::Drop => state.to_mut().document_mut().drop_message(),
+            }
+        } else {
+            match self.fault {
+                None => o.send(peer, GlobalMsg::ServerToServer(msg)),
+                Some(Fault::Truncate) => o.send(
+                    peer,
+                    GlobalMsg::ServerToServer(msg.map_payloads(Bytes::truncated)),
+                ),
+                Some(Fault::BitFlip) => o.send(
+                    peer,
+                    GlobalMsg::ServerToServer(msg.map_payloads(Bytes::bit_flipped)),
+                ),
+                Some(Fault::Replay) => {
+                    o.send(peer, GlobalMsg::ServerToServer(msg.clone()));
+                    if !matches!(msg, ServerMsg::Join | ServerMsg::Leave) {
+                        let previous = state
+                            .to_mut()
+                            .document_mut()
+                            .replace_sent_message(peer.into(), msg);
+                        if let Some(previous) = previous {
+                            o.send(peer, GlobalMsg::ServerToServer(previous));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send a message to all peers, unless we are partitioned from them or they have left.
+    fn broadcast_to_peers(
+        &self,
+        state: &mut Cow<<Self as Actor>::State>,
+        msg: ServerMsg,
+        o: &mut Out<Self>,
+    ) {
+        for peer in &self.peers {
+            self.send_to_peer(state, *peer, msg.clone(), o);
+        }
+    }
+
+    /// Heal a partition, delivering any messages that were held and recovering any that were
+    /// dropped.
+    fn heal(&self, state: &mut Cow<<Self as Actor>::State>, o: &mut Out<Self>) {
+        let held = state.to_mut().document_mut().heal();
+        for (peer, msg) in held.outbound {
+            self.send_to_peer(state, Id::from(peer), msg, o);
+        }
+        for (peer, msg) in held.inbound {
+            self.handle_server_msg(state, Id::from(peer), msg, o);
+        }
+        if held.dropped {
+            // nothing sent across the partition arrived, so neither side knows what the other
+            // is missing
+            let document = state.to_mut().document_mut();
+            document.rewind_last_sent_heads(&held.sent_heads);
+            for peer in &self.partitioned_peers {
+                document.remove_sync_state(usize::from(*peer));
+            }
+            for peer in &self.partitioned_peers {
+                self.send_to_peer(state, *peer, ServerMsg::Join, o);
+            }
+            self.sync(state, o)
+        }
+    }
+
     /// Handle generating a sync message after some changes have been made.
     fn sync(&self, state: &mut Cow<<Self as Actor>::State>, o: &mut Out<Self>) {
+        if !state.document().is_joined() {
+            // nobody to sync with until we join
+            return;
+        }
         match &self.sync_method {
             SyncMethod::Changes => {
                 let new_changes_from_us = state
@@ -189,12 +825,25 @@ impl<A: Application> Server<A> {
                     .map(|c| Bytes(c.raw_bytes().to_vec()))
                     .collect::<Vec<_>>();
                 if !new_changes_from_us.is_empty() {
-                    o.broadcast(
-                        &self.peers,
-                        &GlobalMsg::ServerToServer(ServerMsg::SyncChangeRaw {
-                            missing_changes_bytes: new_changes_from_us,
-                        }),
-                    );
+                    if self.out_of_order_changes {
+                        for change_bytes in new_changes_from_us {
+                            self.broadcast_to_peers(
+                                state,
+                                ServerMsg::SyncChangeRaw {
+                                    missing_changes_bytes: vec![change_bytes],
+                                },
+                                o,
+                            );
+                        }
+                    } else {
+                        self.broadcast_to_peers(
+                            state,
+                            ServerMsg::SyncChangeRaw {
+                                missing_changes_bytes: new_changes_from_us,
+                            },
+                            o,
+                        );
+                    }
                     state.to_mut().document_mut().update_last_sent_heads();
                 }
             }
@@ -206,33 +855,68 @@ impl<A: Application> Server<A> {
                         .document_mut()
                         .generate_sync_message((*peer).into())
                     {
-                        o.send(
+                        self.send_to_peer(
+                            state,
                             *peer,
-                            GlobalMsg::ServerToServer(ServerMsg::SyncMessageRaw {
+                            ServerMsg::SyncMessageRaw {
                                 message_bytes: Bytes(message.encode()),
-                            }),
+                            },
+                            o,
                         )
                     }
                 }
             }
             SyncMethod::SaveLoad => {
-                let state = state.to_mut();
-                let bytes = state.document_mut().save();
-                state.document_mut().update_last_sent_heads();
-                o.broadcast(
-                    &self.peers,
-                    &GlobalMsg::ServerToServer(ServerMsg::SyncSaveLoadRaw {
+                let document = state.to_mut().document_mut();
+                let bytes = document.save();
+                document.update_last_sent_heads();
+                self.broadcast_to_peers(
+                    state,
+                    ServerMsg::SyncSaveLoadRaw {
                         doc_bytes: Bytes(bytes),
-                    }),
+                    },
+                    o,
                 );
             }
+            SyncMethod::IncrementalSaveLoad => {
+                let bytes = state.to_mut().document_mut().save_incremental();
+                if !bytes.is_empty() {
+                    self.broadcast_to_peers(
+                        state,
+                        ServerMsg::SyncIncrementalSaveLoadRaw {
+                            chunk_bytes: Bytes(bytes),
+                        },
+                        o,
+                    );
+                }
+            }
         }
     }
 
-    fn restart(&self, state: &mut Cow<<Self as Actor>::State>) {
-        let document = state.to_mut().document_mut();
-        let bytes = document.save();
-        document.load(&bytes);
-        document.reload_sync_states()
+    fn restart(&self, state: &mut Cow<<Self as Actor>::State>, o: &mut Out<Self>) {
+        match self.restart_mode {
+            RestartMode::SaveLoad => {
+                let document = state.to_mut().document_mut();
+                let bytes = document.save();
+                document.load(&bytes);
+                document.reload_sync_states();
+                self.deliver_patches(state, true)
+            }
+            RestartMode::IncrementalSaveLoad => {
+                let document = state.to_mut().document_mut();
+                let bytes = document.save_incremental_after(&[]);
+                document.load_from_incremental(&bytes);
+                document.reload_sync_states();
+                self.deliver_patches(state, true)
+            }
+            RestartMode::Crash => {
+                let document = state.to_mut().document_mut();
+                document.rollback_to_persisted();
+                document.reload_sync_states();
+                self.deliver_patches(state, true);
+                // reconnect to our peers to recover anything that was lost
+                self.sync(state, o)
+            }
+        }
     }
 }
@@ -0,0 +1,232 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use stateright::actor::{ActorModelState, Envelope, Id, Network};
+use stateright::{Rewrite, RewritePlan};
+
+use crate::client::{Application, DerefDocument};
+use crate::document::{materialize, IdRewrite};
+use crate::drive::Drive;
+use crate::global::{GlobalActor, GlobalActorState, GlobalMsg, GlobalTimer};
+use crate::server::Timer;
+
+/// Most orders of servers that sort the same to try, beyond which the servers are left in the
+/// order they sorted in.
+///
+/// Each order rewrites every document, so trying all of them grows with the factorial of the
+/// number of tied servers. Representatives of states with more ties depend on where the servers
+/// started, so fewer states are found to be symmetric, but every state is still explored.
+const MAX_ORDERS: usize = 6;
+
+/// Get the representative of a state under symmetry of the servers.
+///
+/// Servers are sorted by their document content along with the states of their clients, and
+/// clients move with the server they belong to. Servers that sort the same are tried in each order,
+/// up to [`MAX_ORDERS`] orders, keeping the smallest resulting state, so the representative doesn't
+/// depend on where they started. Documents then have their actor ids, the changes in their sync states and peer indices
+/// remapped to match the new positions, as do the payloads of messages.
+///
+/// This assumes that the servers are interchangeable: built from the same application, with
+/// matching driver sets and no per-server options such as relays, faults or late joiners. The
+/// model checks this when symmetry is enabled.
+///
+/// States that can't be rewritten, such as those with queued changes, are their own
+/// representative.
+pub fn representative<A, D, H>(
+    state: &ActorModelState<GlobalActor<A, D>, H>,
+) -> ActorModelState<GlobalActor<A, D>, H>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Clone + Hash,
+{
+    let servers = state
+        .actor_states
+        .iter()
+        .filter(|s| matches!(&***s, GlobalActorState::Server(_)))
+        .count();
+    if servers == 0 {
+        return state.clone();
+    }
+    // clients are added in equally sized blocks after the servers, one block per server
+    let clients_per_server = (state.actor_states.len() - servers) / servers;
+    let owner = |client: usize| (client - servers) / clients_per_server.max(1);
+
+    let group_keys = (0..servers)
+        .map(|server| {
+            let mut hasher = DefaultHasher::new();
+            if let GlobalActorState::Server(s) = &*state.actor_states[server] {
+                materialize(s.document()).hash(&mut hasher);
+            }
+            for client in servers..state.actor_states.len() {
+                if owner(client) == server {
+                    state.actor_states[client].hash(&mut hasher);
+                }
+            }
+            hasher.finish()
+        })
+        .collect::<Vec<_>>();
+
+    let mut server_order = (0..servers).collect::<Vec<_>>();
+    server_order.sort_by_key(|&server| group_keys[server]);
+    // servers with the same key can go in any order among themselves
+    let mut server_orders = vec![Vec::new()];
+    for tied in server_order.chunk_by(|a, b| group_keys[*a] == group_keys[*b]) {
+        server_orders = server_orders
+            .into_iter()
+            .flat_map(|order| {
+                permutations(tied).into_iter().map(move |tied| {
+                    let mut order = order.clone();
+                    order.extend(tied);
+                    order
+                })
+            })
+            .collect();
+    }
+    if server_orders.len() > MAX_ORDERS {
+        // too many ties to try them all, so keep the order they sorted in
+        server_orders = vec![server_order];
+    }
+
+    server_orders
+        .into_iter()
+        .map(|server_order| rewrite_state(state, &server_order, &owner))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|candidates| {
+            candidates.into_iter().min_by_key(|candidate| {
+                let mut hasher = DefaultHasher::new();
+                candidate.hash(&mut hasher);
+                hasher.finish()
+            })
+        })
+        .unwrap_or_else(|| state.clone())
+}
+
+/// Rewrite a state with the servers in the given order, followed by their clients.
+fn rewrite_state<A, D, H>(
+    state: &ActorModelState<GlobalActor<A, D>, H>,
+    server_order: &[usize],
+    owner: &dyn Fn(usize) -> usize,
+) -> Option<ActorModelState<GlobalActor<A, D>, H>>
+where
+    A: Application,
+    D: Drive<A>,
+    H: Clone,
+{
+    let servers = server_order.len();
+    // the old index of the actor at each new position, and the reverse
+    let mut order = server_order.to_vec();
+    for &server in server_order {
+        order.extend((servers..state.actor_states.len()).filter(|&client| owner(client) == server));
+    }
+    let mut new_indices = vec![0; order.len()];
+    for (new, old) in order.iter().enumerate() {
+        new_indices[*old] = new;
+    }
+    let plan = RewritePlan::<Id, _>::from_values_to_sort(&new_indices);
+
+    // ids outside of the actors, such as the actor for the initial change, stay the same
+    let mut rewrite = IdRewrite::new(new_indices);
+    // all histories are needed before anything referring to their changes can be rewritten
+    for actor_state in &state.actor_states {
+        if let GlobalActorState::Server(s) = &**actor_state {
+            rewrite.add_document(s.document())?;
+        }
+    }
+
+    let mut representative = state.clone();
+    representative.actor_states = order
+        .iter()
+        .map(|old| rewrite_actor_state(&state.actor_states[*old], &mut rewrite).map(Arc::new))
+        .collect::<Option<_>>()?;
+    representative.timers_set = plan.reindex(&state.timers_set);
+    representative.network = rewrite_network(&state.network, &rewrite)?;
+    Some(representative)
+}
+
+/// Rewrite the messages in a network, moving them between the new positions of actors and
+/// remapping their payloads, returning `None` if a payload can't be rewritten.
+fn rewrite_network<A: Application>(
+    network: &Network<GlobalMsg<A>>,
+    rewrite: &IdRewrite,
+) -> Option<Network<GlobalMsg<A>>> {
+    let id = |id: Id| Id::from(rewrite.id(usize::from(id)));
+    let envelopes = network
+        .iter_all()
+        .map(|envelope| {
+            let msg = match envelope.msg {
+                GlobalMsg::ServerToServer(msg) => GlobalMsg::ServerToServer(rewrite.message(msg)?),
+                // messages with clients don't refer to other actors
+                msg @ GlobalMsg::ClientToServer(_) => msg.clone(),
+            };
+            Some(Envelope {
+                src: id(envelope.src),
+                dst: id(envelope.dst),
+                msg,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // messages between each pair of actors keep their order, which is all an ordered network has
+    Some(match network {
+        Network::UnorderedDuplicating(_) => Network::new_unordered_duplicating(envelopes),
+        Network::UnorderedNonDuplicating(_) => Network::new_unordered_nonduplicating(envelopes),
+        Network::Ordered(_) => Network::new_ordered(envelopes),
+    })
+}
+
+/// All orderings of the given items.
+fn permutations(items: &[usize]) -> Vec<Vec<usize>> {
+    if items.len() <= 1 {
+        return vec![items.to_vec()];
+    }
+    let mut result = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let mut rest = items.to_vec();
+        rest.remove(i);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, *item);
+            result.push(permutation);
+        }
+    }
+    result
+}
+
+fn rewrite_actor_state<D, A>(
+    state: &GlobalActorState<D, A>,
+    rewrite: &mut IdRewrite,
+) -> Option<GlobalActorState<D, A>>
+where
+    A: Application,
+    D: Drive<A>,
+{
+    match state {
+        GlobalActorState::Client(c) => Some(GlobalActorState::Client(c.clone())),
+        GlobalActorState::Server(s) => {
+            let mut s = s.clone();
+            let document = s.document().rewrite_ids(rewrite)?;
+            *s.document_mut() = document;
+            Some(GlobalActorState::Server(s))
+        }
+    }
+}
+
+impl Rewrite<Id> for GlobalTimer {
+    fn rewrite<S>(&self, plan: &RewritePlan<Id, S>) -> Self {
+        match self {
+            GlobalTimer::Server(Timer::ResetSyncState(peer)) => GlobalTimer::Server(
+                Timer::ResetSyncState(usize::from(plan.rewrite(&Id::from(*peer)))),
+            ),
+            GlobalTimer::Server(Timer::SnapshotSyncState(peer)) => GlobalTimer::Server(
+                Timer::SnapshotSyncState(usize::from(plan.rewrite(&Id::from(*peer)))),
+            ),
+            GlobalTimer::Server(Timer::DeliverMessage(peer)) => GlobalTimer::Server(
+                Timer::DeliverMessage(usize::from(plan.rewrite(&Id::from(*peer)))),
+            ),
+            GlobalTimer::Server(Timer::LoseMessage(peer)) => GlobalTimer::Server(
+                Timer::LoseMessage(usize::from(plan.rewrite(&Id::from(*peer)))),
+            ),
+            GlobalTimer::Server(timer) => GlobalTimer::Server(timer.clone()),
+        }
+    }
+}